@@ -1,7 +1,8 @@
 use std::{cell::RefCell, rc::Rc};
 
 use environment_builder::{
-    EnvironmentProvider, ReferenceEnvironmentParameters, ReferenceEnvironmentProvider,
+    safe_color, EnvironmentProvider, FactorLayer, ReferenceEnvironmentParameters,
+    ReferenceEnvironmentProvider,
 };
 use gtk4::{cairo::Context, prelude::WidgetExt, DrawingArea};
 use vislayers::{
@@ -12,7 +13,7 @@ use vislayers::{
 
 struct EnvironmentProviderWrapped<T: EnvironmentProvider> {
     provider: T,
-    layers: Vec<(String, f64)>,
+    layers: Vec<(FactorLayer, f64)>,
 }
 
 impl<T: EnvironmentProvider> Layer for EnvironmentProviderWrapped<T> {
@@ -33,6 +34,9 @@ impl<T: EnvironmentProvider> Layer for EnvironmentProviderWrapped<T> {
         let grayscale_colormap =
             SimpleColorMap::new(vec![[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]], vec![0.0, 1.0]);
 
+        // color of the non-finite values
+        let fallback_color = [1.0, 0.0, 1.0];
+
         let size = 3;
 
         let draw_dot = |ix: i32, iy: i32, color: [f64; 3], alpha: f64| {
@@ -78,62 +82,30 @@ impl<T: EnvironmentProvider> Layer for EnvironmentProviderWrapped<T> {
                     let x = rect.min_x + prop_x * rect.width();
                     let y = rect.min_y + prop_y * rect.height();
 
-                    let environment = self.provider.get_factors(x, y);
-                    if let Some(environment) = environment {
-                        match layer.as_str() {
-                            "temperature_surface" => {
-                                let temperature = environment.temperature_surface;
-                                let color = temperature_colormap.get_color(temperature);
-                                draw_dot(ix, iy, color, *alpha);
-                            }
-                            "primitive_shelf" => {
-                                let primitive_shelf = environment.primitive_elevation_factors.shelf;
-                                let color = grayscale_colormap.get_color(primitive_shelf + 1.0);
-                                draw_dot(ix, iy, color, *alpha);
-                            }
-                            "primitive_persistence" => {
-                                let primitive_persistence =
-                                    environment.primitive_elevation_factors.persistence;
-                                let color =
-                                    grayscale_colormap.get_color(primitive_persistence.normalized);
-                                draw_dot(ix, iy, color, *alpha);
-                            }
-                            "primitive_elevation" => {
-                                let primitive_elevation =
-                                    environment.primitive_elevation_factors.elevation;
-                                let color =
-                                    grayscale_colormap.get_color(primitive_elevation.normalized);
-                                draw_dot(ix, iy, color, *alpha);
-                            }
-                            "atmosphere_pressure_normalized" => {
-                                let atmosphere_pressure_normalized =
-                                    environment.atmosphere_pressure_normalized;
-                                let color =
-                                    grayscale_colormap.get_color(atmosphere_pressure_normalized);
-                                draw_dot(ix, iy, color, *alpha);
-                            }
-                            "atmosphere_pressure_current" => {
-                                draw_arrow(
-                                    ix,
-                                    iy,
-                                    environment.atmosphere_current_angle,
-                                    [1.0, 0.0, 0.0],
-                                    *alpha,
-                                    environment.atmosphere_current_magnitude,
-                                );
-                            }
-                            "ocean_current" => {
-                                draw_arrow(
-                                    ix,
-                                    iy,
-                                    environment.ocean_current_angle,
-                                    [1.0, 0.0, 0.0],
-                                    *alpha,
-                                    environment.ocean_current_magnitude,
-                                );
-                            }
-                            _ => break,
+                    let Some(environment) = self.provider.get_factors(x, y) else {
+                        continue;
+                    };
+                    if let Some((angle, magnitude)) = environment.get_vector(*layer) {
+                        draw_arrow(ix, iy, angle, [1.0, 0.0, 0.0], *alpha, magnitude);
+                    } else if let Some(value) = environment.get_scalar(*layer) {
+                        let color = match layer {
+                            FactorLayer::TemperatureSurface => safe_color(
+                                value,
+                                |v| temperature_colormap.get_color(v),
+                                fallback_color,
+                            ),
+                            FactorLayer::PrimitiveShelf => safe_color(
+                                value + 1.0,
+                                |v| grayscale_colormap.get_color(v),
+                                fallback_color,
+                            ),
+                            _ => safe_color(
+                                value,
+                                |v| grayscale_colormap.get_color(v),
+                                fallback_color,
+                            ),
                         };
+                        draw_dot(ix, iy, color, *alpha);
                     }
                 }
             }
@@ -149,11 +121,11 @@ fn main() {
         Rc::new(RefCell::new(EnvironmentProviderWrapped {
             provider: environment_provider,
             layers: vec![
-                ("primitive_elevation".to_string(), 1.0),
-                // ("ocean_current".to_string(), 0.5),
-                // ("temperature_surface".to_string(), 0.5),
-                //("atmosphere_pressure_normalized".to_string(), 0.5),
-                ("atmosphere_pressure_current".to_string(), 0.5),
+                (FactorLayer::PrimitiveElevation, 1.0),
+                // (FactorLayer::OceanCurrent, 0.5),
+                // (FactorLayer::TemperatureSurface, 0.5),
+                // (FactorLayer::AtmospherePressureNormalized, 0.5),
+                (FactorLayer::AtmosphereCurrent, 0.5),
             ],
         })),
         0,
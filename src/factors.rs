@@ -0,0 +1,902 @@
+//! Factors computed at each point and utilities over them
+
+use crate::{smoothstep, ELEVATION_NORMALIZED_LIMITS};
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ValueRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ValueWithNormalized {
+    pub value: f64,
+    pub normalized: f64,
+}
+
+/// normalized: [0.0, 1.0] is mapped onto [range.min, range.max]
+pub fn remap_normalized(normalized: f64, range: ValueRange) -> f64 {
+    range.min + normalized * (range.max - range.min)
+}
+
+/// normalized: [-1.0, 1.0] is mapped onto [range.min, range.max]
+pub fn remap_signed_normalized(normalized: f64, range: ValueRange) -> f64 {
+    remap_normalized(normalized * 0.5 + 0.5, range)
+}
+
+impl ValueWithNormalized {
+    pub fn from_normalized(normalized: f64, range: ValueRange) -> Self {
+        Self {
+            value: remap_normalized(normalized, range),
+            normalized,
+        }
+    }
+
+    /// normalized: [-1.0, 1.0] is mapped onto [range.min, range.max]
+    pub fn from_signed_normalized(normalized: f64, range: ValueRange) -> Self {
+        Self {
+            value: remap_signed_normalized(normalized, range),
+            normalized,
+        }
+    }
+
+    /// Value of the normalized [0.0, 1.0] in `new_range` (as from_normalized,
+    /// e.g. the persistence in another display range)
+    pub fn remap(&self, new_range: ValueRange) -> f64 {
+        remap_normalized(self.normalized, new_range)
+    }
+
+    /// Value of the normalized [-1.0, 1.0] in `new_range` (as from_signed_normalized,
+    /// e.g. the elevation in another display range)
+    pub fn remap_signed(&self, new_range: ValueRange) -> f64 {
+        remap_signed_normalized(self.normalized, new_range)
+    }
+
+    /// Inverse of from_signed_normalized: value in [range.min, range.max] -> normalized [-1.0, 1.0]
+    pub fn from_signed_value(value: f64, range: ValueRange) -> Self {
+        Self {
+            value,
+            normalized: (value - range.min) / (range.max - range.min) * 2.0 - 1.0,
+        }
+    }
+}
+
+/// primitive_elevation = primitive_land_base + primitive_shelf
+/// (if primitive_elevation > 0.0, primitive_elevation = primitive_elevation.powf(primitive_land_power),
+/// or land_shaping_fn(primitive_elevation) if land_shaping_fn is set;
+/// blended by smoothstep within +-shelf_blend_smoothness if it is positive)
+/// (if primitive_abyssal_depth is set, primitive_elevation = primitive_elevation.max(-primitive_abyssal_depth))
+/// (then multiplied by land_elevation_scale if positive, or by ocean_depth_scale otherwise)
+/// (finally clamped to [-1.0, 1.0], so that the value stays in primitive_elevation_range)
+#[derive(Debug, Clone, Copy)]
+pub struct PrimitiveElevationFactors {
+    /// [-primitive_shelf_depth,0.0] (primitive_shelf_power applied)
+    pub shelf: f64,
+    /// [0.0,1.0] (normalized)
+    pub persistence: ValueWithNormalized,
+    /// [0.0,1.0]
+    pub land_base: f64,
+    /// [-1.0, 1.0] (normalized)
+    pub elevation: ValueWithNormalized,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EnvironmentFactors {
+    /// [-PI/2, PI/2] (radian) (calculated by virtual_latitude_fn)
+    pub virtual_latitude: f64,
+    /// (degree) (calculated by temperature_surface_fn or temperature_profile,
+    /// then temperature_floor and temperature_clamp are applied if set)
+    pub temperature_surface: f64,
+    /// Expected swing between day and night temperature (degree)
+    /// (diurnal_range_base + diurnal_range_modulation * dryness, where dryness is the
+    /// continentalness on land and 0.0 over the ocean)
+    pub diurnal_range: f64,
+
+    /// [-atmosphere_pressure_noise_prop, 1.0], or [0.0, 1.0] with PressureNoiseBlend::Unsigned
+    /// (at sea level, used for the atmosphere current)
+    ///
+    /// The atmosphere fields (pressure, current and speed) are None if compute_atmosphere
+    /// is disabled
+    pub sea_level_pressure_normalized: Option<f64>,
+    /// sea_level_pressure_normalized reduced by the altitude of the surface toward its lower
+    /// bound (see pressure_altitude_scale)
+    pub atmosphere_pressure_normalized: Option<f64>,
+    /// (where the pressure is flat, the magnitude is 0.0 and the angle carries no direction)
+    pub atmosphere_current_angle: Option<f64>,
+    pub atmosphere_current_magnitude: Option<f64>,
+    /// (m/s) |atmosphere_current_magnitude| * wind_speed_scale
+    pub atmosphere_current_speed: Option<f64>,
+
+    /// [PrimitiveElevationFactors]
+    pub primitive_elevation_factors: PrimitiveElevationFactors,
+    /// (radian) (None if compute_ocean is disabled)
+    pub ocean_current_angle: Option<f64>,
+    /// [0.0, 1.0] (None if compute_ocean is disabled)
+    pub ocean_current_magnitude: Option<f64>,
+}
+
+/// Classification of the surface by the elevation (see EnvironmentFactors::water_class)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WaterClass {
+    /// At or above the sea level
+    Land,
+    /// Submerged, within the shelf depth below the sea level (shallow water)
+    Shelf,
+    /// Submerged deeper than the shelf depth
+    DeepOcean,
+}
+
+/// Isolines of a factor at a threshold
+#[derive(Debug, Clone)]
+pub struct Contour {
+    pub threshold: f64,
+    /// Polylines in world coordinates (closed ones end with their first point)
+    pub lines: Vec<Vec<(f64, f64)>>,
+}
+
+impl EnvironmentFactors {
+    /// Wind speed (m/s) estimated from the atmosphere current
+    /// (None if compute_atmosphere is disabled)
+    pub fn wind_speed_ms(&self) -> Option<f64> {
+        self.atmosphere_current_speed
+    }
+
+    /// Whether |ocean_current_magnitude| exceeds `threshold`
+    /// (e.g. current_ignorable_magnitude of the parameters; false if the current is not computed)
+    pub fn has_significant_ocean_current(&self, threshold: f64) -> bool {
+        self.ocean_current_magnitude
+            .is_some_and(|magnitude| magnitude.abs() > threshold)
+    }
+
+    /// Whether |atmosphere_current_magnitude| exceeds `threshold`
+    /// (e.g. current_ignorable_magnitude of the parameters; false if the current is not computed)
+    pub fn has_significant_atmosphere_current(&self, threshold: f64) -> bool {
+        self.atmosphere_current_magnitude
+            .is_some_and(|magnitude| magnitude.abs() > threshold)
+    }
+
+    /// Value of a scalar layer (None for the vector layers and the layers not computed)
+    pub fn get_scalar(&self, layer: FactorLayer) -> Option<f64> {
+        let elevation_factors = &self.primitive_elevation_factors;
+        match layer {
+            FactorLayer::VirtualLatitude => Some(self.virtual_latitude),
+            FactorLayer::TemperatureSurface => Some(self.temperature_surface),
+            FactorLayer::DiurnalRange => Some(self.diurnal_range),
+            FactorLayer::SeaLevelPressureNormalized => self.sea_level_pressure_normalized,
+            FactorLayer::AtmospherePressureNormalized => self.atmosphere_pressure_normalized,
+            FactorLayer::AtmosphereCurrentSpeed => self.atmosphere_current_speed,
+            FactorLayer::PrimitiveShelf => Some(elevation_factors.shelf),
+            FactorLayer::PrimitivePersistence => Some(elevation_factors.persistence.normalized),
+            FactorLayer::PrimitiveLandBase => Some(elevation_factors.land_base),
+            FactorLayer::PrimitiveElevation => Some(elevation_factors.elevation.normalized),
+            FactorLayer::AtmosphereCurrent | FactorLayer::OceanCurrent => None,
+        }
+    }
+
+    /// [0.0, 1.0] rising smoothly from the deepest ocean (0.0) through the shoreline (0.5)
+    /// to the highest land (1.0), e.g. for blending textures in a shader.
+    /// `sea_level` is a normalized elevation (0.0: the sea level of the primitive elevation).
+    /// The elevation is rescaled to h in [-1.0, 1.0] linearly on each side of the sea level
+    /// (-1.0 at the lowest, 0.0 at the sea level, 1.0 at the highest normalized elevation),
+    /// and the landness is smoothstep(-1.0, 1.0, h), steepest at the shoreline.
+    pub fn landness(&self, sea_level: f64) -> f64 {
+        let (min, max) = ELEVATION_NORMALIZED_LIMITS;
+        let elevation = self.primitive_elevation_factors.elevation.normalized;
+        let h = if elevation >= sea_level {
+            (elevation - sea_level) / (max - sea_level).max(f64::EPSILON)
+        } else {
+            (elevation - sea_level) / (sea_level - min).max(f64::EPSILON)
+        };
+        smoothstep(-1.0, 1.0, h)
+    }
+
+    /// Land, shelf or deep ocean by the normalized elevation.
+    /// `sea_level` is a normalized elevation (0.0: the sea level of the primitive elevation),
+    /// and `shelf_depth` is the normalized depth below it classified as the shelf
+    /// (e.g. shelf_class_depth of the parameters).
+    pub fn water_class(&self, sea_level: f64, shelf_depth: f64) -> WaterClass {
+        let elevation = self.primitive_elevation_factors.elevation.normalized;
+        if elevation >= sea_level {
+            WaterClass::Land
+        } else if elevation >= sea_level - shelf_depth {
+            WaterClass::Shelf
+        } else {
+            WaterClass::DeepOcean
+        }
+    }
+
+    /// Factors as a feature vector in the order of FEATURE_NAMES
+    /// (the currents are split into x and y components: magnitude * (cos, sin) of the angle;
+    /// the factors not computed are NaN)
+    pub fn to_feature_array(&self) -> [f32; FEATURE_COUNT] {
+        let elevation_factors = &self.primitive_elevation_factors;
+        let nan = |value: Option<f64>| value.unwrap_or(f64::NAN);
+        let (atmosphere_x, atmosphere_y) = self
+            .get_vector(FactorLayer::AtmosphereCurrent)
+            .map_or((f64::NAN, f64::NAN), |(angle, magnitude)| {
+                (magnitude * angle.cos(), magnitude * angle.sin())
+            });
+        let (ocean_x, ocean_y) = self
+            .get_vector(FactorLayer::OceanCurrent)
+            .map_or((f64::NAN, f64::NAN), |(angle, magnitude)| {
+                (magnitude * angle.cos(), magnitude * angle.sin())
+            });
+        [
+            elevation_factors.elevation.normalized,
+            elevation_factors.elevation.value,
+            elevation_factors.shelf,
+            elevation_factors.persistence.normalized,
+            elevation_factors.land_base,
+            self.virtual_latitude,
+            self.temperature_surface,
+            self.diurnal_range,
+            nan(self.sea_level_pressure_normalized),
+            nan(self.atmosphere_pressure_normalized),
+            atmosphere_x,
+            atmosphere_y,
+            nan(self.atmosphere_current_speed),
+            ocean_x,
+            ocean_y,
+        ]
+        .map(|value| value as f32)
+    }
+
+    /// (angle, magnitude) of a vector layer (None for the scalar layers and the layers
+    /// not computed)
+    pub fn get_vector(&self, layer: FactorLayer) -> Option<(f64, f64)> {
+        match layer {
+            FactorLayer::AtmosphereCurrent => Some((
+                self.atmosphere_current_angle?,
+                self.atmosphere_current_magnitude?,
+            )),
+            FactorLayer::OceanCurrent => {
+                Some((self.ocean_current_angle?, self.ocean_current_magnitude?))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Length of [EnvironmentFactors::to_feature_array]
+pub const FEATURE_COUNT: usize = 15;
+
+/// Names of the features of [EnvironmentFactors::to_feature_array], in order
+pub const FEATURE_NAMES: [&str; FEATURE_COUNT] = [
+    "elevation_normalized",
+    "elevation",
+    "primitive_shelf",
+    "primitive_persistence_normalized",
+    "primitive_land_base",
+    "virtual_latitude",
+    "temperature_surface",
+    "diurnal_range",
+    "sea_level_pressure_normalized",
+    "atmosphere_pressure_normalized",
+    "atmosphere_current_x",
+    "atmosphere_current_y",
+    "atmosphere_current_speed",
+    "ocean_current_x",
+    "ocean_current_y",
+];
+
+/// Layer of [EnvironmentFactors] for rendering (see get_scalar and get_vector)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FactorLayer {
+    VirtualLatitude,
+    TemperatureSurface,
+    DiurnalRange,
+    SeaLevelPressureNormalized,
+    AtmospherePressureNormalized,
+    AtmosphereCurrentSpeed,
+    PrimitiveShelf,
+    /// persistence.normalized
+    PrimitivePersistence,
+    PrimitiveLandBase,
+    /// elevation.normalized
+    PrimitiveElevation,
+    /// Vector: atmosphere_current_angle and atmosphere_current_magnitude
+    AtmosphereCurrent,
+    /// Vector: ocean_current_angle and ocean_current_magnitude
+    OceanCurrent,
+}
+
+/// Land and ocean area (m^2)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AreaBreakdown {
+    pub land_area: f64,
+    pub ocean_area: f64,
+}
+
+impl AreaBreakdown {
+    /// Proportion of land in the valid area [0.0, 1.0] (0.0 if there is no valid area)
+    pub fn land_ratio(&self) -> f64 {
+        let total = self.land_area + self.ocean_area;
+        if total > 0.0 {
+            self.land_area / total
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Vector field of a current (see streamlines)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurrentField {
+    /// ocean_current_angle and ocean_current_magnitude
+    Ocean,
+    /// atmosphere_current_angle and atmosphere_current_magnitude
+    Atmosphere,
+}
+
+/// Sample of the atmosphere column at an altitude (see vertical_profile)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColumnSample {
+    /// (m) above the sea level
+    pub altitude: f64,
+    /// (degree)
+    pub temperature: f64,
+    /// sea_level_pressure_normalized reduced by the altitude
+    /// (None if compute_atmosphere is disabled)
+    pub pressure_normalized: Option<f64>,
+}
+
+/// Estimated amount of work of sampling a region (see estimate_cost)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GenerationCost {
+    /// Number of points sampled
+    pub points: u64,
+    /// Number of evaluations of the noise sources (one octave of one channel each)
+    pub noise_samples: u64,
+    /// Number of gradients of the sea level pressure taken for the atmosphere current
+    /// (one per point; included in noise_samples)
+    pub gradient_passes: u64,
+}
+
+/// Per-field difference (b - a) of two [EnvironmentFactors]
+/// (angles are wrapped into [-PI, PI]; None unless the field is computed in both)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FactorDiff {
+    pub virtual_latitude: f64,
+    pub temperature_surface: f64,
+    pub diurnal_range: f64,
+
+    pub sea_level_pressure_normalized: Option<f64>,
+    pub atmosphere_pressure_normalized: Option<f64>,
+    pub atmosphere_current_angle: Option<f64>,
+    pub atmosphere_current_magnitude: Option<f64>,
+    pub atmosphere_current_speed: Option<f64>,
+
+    pub primitive_shelf: f64,
+    pub primitive_persistence: f64,
+    pub primitive_land_base: f64,
+    /// (m)
+    pub primitive_elevation: f64,
+    pub primitive_elevation_normalized: f64,
+
+    pub ocean_current_angle: Option<f64>,
+    pub ocean_current_magnitude: Option<f64>,
+}
+
+/// Difference of angles (b - a) wrapped into [-PI, PI]
+pub fn angle_difference(a: f64, b: f64) -> f64 {
+    use std::f64::consts::PI;
+    (b - a + PI).rem_euclid(2.0 * PI) - PI
+}
+
+/// Unwraps a row-major grid of `cols` x `rows` angles (e.g. atmosphere_current_angle or
+/// ocean_current_angle) by adding multiples of 2 * PI, so that each value differs by less
+/// than PI from its left neighbor (or from the one above for the first column), for
+/// interpolating and contouring across the 2 * PI seam.
+/// Around a vortex the angle cannot be continuous everywhere, and a seam remains between
+/// some vertical neighbors. NaN values stay NaN and are skipped as the reference.
+pub fn angle_unwrap_grid(angles: &[f64], cols: usize, rows: usize) -> Vec<f64> {
+    let mut unwrapped = angles.to_vec();
+    for iy in 0..rows {
+        for ix in 0..cols {
+            let i = iy * cols + ix;
+            let left = (ix > 0).then(|| unwrapped[i - 1]);
+            let above = (iy > 0).then(|| unwrapped[i - cols]);
+            let reference = left
+                .filter(|a| !a.is_nan())
+                .or(above.filter(|a| !a.is_nan()));
+            if let Some(reference) = reference {
+                unwrapped[i] = reference + angle_difference(reference, angles[i]);
+            }
+        }
+    }
+    unwrapped
+}
+
+pub fn diff_factors(a: &EnvironmentFactors, b: &EnvironmentFactors) -> FactorDiff {
+    let (pa, pb) = (
+        &a.primitive_elevation_factors,
+        &b.primitive_elevation_factors,
+    );
+    let diff = |a: Option<f64>, b: Option<f64>| Some(b? - a?);
+    let diff_angle = |a: Option<f64>, b: Option<f64>| Some(angle_difference(a?, b?));
+    FactorDiff {
+        virtual_latitude: b.virtual_latitude - a.virtual_latitude,
+        temperature_surface: b.temperature_surface - a.temperature_surface,
+        diurnal_range: b.diurnal_range - a.diurnal_range,
+
+        sea_level_pressure_normalized: diff(
+            a.sea_level_pressure_normalized,
+            b.sea_level_pressure_normalized,
+        ),
+        atmosphere_pressure_normalized: diff(
+            a.atmosphere_pressure_normalized,
+            b.atmosphere_pressure_normalized,
+        ),
+        atmosphere_current_angle: diff_angle(
+            a.atmosphere_current_angle,
+            b.atmosphere_current_angle,
+        ),
+        atmosphere_current_magnitude: diff(
+            a.atmosphere_current_magnitude,
+            b.atmosphere_current_magnitude,
+        ),
+        atmosphere_current_speed: diff(a.atmosphere_current_speed, b.atmosphere_current_speed),
+
+        primitive_shelf: pb.shelf - pa.shelf,
+        primitive_persistence: pb.persistence.value - pa.persistence.value,
+        primitive_land_base: pb.land_base - pa.land_base,
+        primitive_elevation: pb.elevation.value - pa.elevation.value,
+        primitive_elevation_normalized: pb.elevation.normalized - pa.elevation.normalized,
+
+        ocean_current_angle: diff_angle(a.ocean_current_angle, b.ocean_current_angle),
+        ocean_current_magnitude: diff(a.ocean_current_magnitude, b.ocean_current_magnitude),
+    }
+}
+
+/// Per-field tolerances of factors_approx_eq (the fields of [FactorDiff];
+/// the angles are compared by their wrapped difference)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FactorTolerance {
+    pub virtual_latitude: f64,
+    pub temperature_surface: f64,
+    pub diurnal_range: f64,
+
+    pub sea_level_pressure_normalized: f64,
+    pub atmosphere_pressure_normalized: f64,
+    pub atmosphere_current_angle: f64,
+    pub atmosphere_current_magnitude: f64,
+    pub atmosphere_current_speed: f64,
+
+    pub primitive_shelf: f64,
+    pub primitive_persistence: f64,
+    pub primitive_land_base: f64,
+    /// (m)
+    pub primitive_elevation: f64,
+    pub primitive_elevation_normalized: f64,
+
+    pub ocean_current_angle: f64,
+    pub ocean_current_magnitude: f64,
+}
+
+impl FactorTolerance {
+    /// The same tolerance for every field
+    pub fn uniform(tolerance: f64) -> Self {
+        Self {
+            virtual_latitude: tolerance,
+            temperature_surface: tolerance,
+            diurnal_range: tolerance,
+            sea_level_pressure_normalized: tolerance,
+            atmosphere_pressure_normalized: tolerance,
+            atmosphere_current_angle: tolerance,
+            atmosphere_current_magnitude: tolerance,
+            atmosphere_current_speed: tolerance,
+            primitive_shelf: tolerance,
+            primitive_persistence: tolerance,
+            primitive_land_base: tolerance,
+            primitive_elevation: tolerance,
+            primitive_elevation_normalized: tolerance,
+            ocean_current_angle: tolerance,
+            ocean_current_magnitude: tolerance,
+        }
+    }
+}
+
+/// Whether every field of `a` and `b` differs by at most its tolerance in `tol`
+/// (a field that is not computed in both, e.g. a disabled factor, is regarded as equal,
+/// and one computed in only one of them as different)
+pub fn factors_approx_eq(
+    a: &EnvironmentFactors,
+    b: &EnvironmentFactors,
+    tol: &FactorTolerance,
+) -> bool {
+    let close = |a: f64, b: f64, tolerance: f64| (b - a).abs() <= tolerance;
+    let close_angle = |a: f64, b: f64, tolerance: f64| angle_difference(a, b).abs() <= tolerance;
+    let close_opt = |a: Option<f64>, b: Option<f64>, tolerance: f64| match (a, b) {
+        (Some(a), Some(b)) => close(a, b, tolerance),
+        (None, None) => true,
+        _ => false,
+    };
+    let close_angle_opt = |a: Option<f64>, b: Option<f64>, tolerance: f64| match (a, b) {
+        (Some(a), Some(b)) => close_angle(a, b, tolerance),
+        (None, None) => true,
+        _ => false,
+    };
+    let (pa, pb) = (
+        &a.primitive_elevation_factors,
+        &b.primitive_elevation_factors,
+    );
+    close(a.virtual_latitude, b.virtual_latitude, tol.virtual_latitude)
+        && close(
+            a.temperature_surface,
+            b.temperature_surface,
+            tol.temperature_surface,
+        )
+        && close(a.diurnal_range, b.diurnal_range, tol.diurnal_range)
+        && close_opt(
+            a.sea_level_pressure_normalized,
+            b.sea_level_pressure_normalized,
+            tol.sea_level_pressure_normalized,
+        )
+        && close_opt(
+            a.atmosphere_pressure_normalized,
+            b.atmosphere_pressure_normalized,
+            tol.atmosphere_pressure_normalized,
+        )
+        && close_angle_opt(
+            a.atmosphere_current_angle,
+            b.atmosphere_current_angle,
+            tol.atmosphere_current_angle,
+        )
+        && close_opt(
+            a.atmosphere_current_magnitude,
+            b.atmosphere_current_magnitude,
+            tol.atmosphere_current_magnitude,
+        )
+        && close_opt(
+            a.atmosphere_current_speed,
+            b.atmosphere_current_speed,
+            tol.atmosphere_current_speed,
+        )
+        && close(pa.shelf, pb.shelf, tol.primitive_shelf)
+        && close(
+            pa.persistence.value,
+            pb.persistence.value,
+            tol.primitive_persistence,
+        )
+        && close(pa.land_base, pb.land_base, tol.primitive_land_base)
+        && close(
+            pa.elevation.value,
+            pb.elevation.value,
+            tol.primitive_elevation,
+        )
+        && close(
+            pa.elevation.normalized,
+            pb.elevation.normalized,
+            tol.primitive_elevation_normalized,
+        )
+        && close_angle_opt(
+            a.ocean_current_angle,
+            b.ocean_current_angle,
+            tol.ocean_current_angle,
+        )
+        && close_opt(
+            a.ocean_current_magnitude,
+            b.ocean_current_magnitude,
+            tol.ocean_current_magnitude,
+        )
+}
+
+/// `colormap(value)`, or `fallback` if `value` is not finite (NaN or infinite)
+/// so that degenerate factors are rendered as a known color
+pub fn safe_color(value: f64, colormap: impl Fn(f64) -> [f64; 3], fallback: [f64; 3]) -> [f64; 3] {
+    if value.is_finite() {
+        colormap(value)
+    } else {
+        fallback
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use crate::{EnvironmentProvider, ReferenceEnvironmentParameters, ValidModel};
+
+    /// Factors of a provider on analytic noise, with every layer computed
+    fn sample_factors(x: f64, y: f64) -> EnvironmentFactors {
+        let provider = provider_with(
+            WaveNoise::default(),
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        );
+        provider.get_factors(x, y).unwrap()
+    }
+
+    #[test]
+    fn diff_of_identical_factors_is_zero() {
+        let factors = sample_factors(0.3, 0.2);
+        let zero = FactorDiff {
+            sea_level_pressure_normalized: Some(0.0),
+            atmosphere_pressure_normalized: Some(0.0),
+            atmosphere_current_angle: Some(0.0),
+            atmosphere_current_magnitude: Some(0.0),
+            atmosphere_current_speed: Some(0.0),
+            ocean_current_angle: Some(0.0),
+            ocean_current_magnitude: Some(0.0),
+            ..Default::default()
+        };
+        assert_eq!(diff_factors(&factors, &factors), zero);
+    }
+
+    #[test]
+    fn diff_shows_the_changed_field() {
+        let a = sample_factors(0.3, 0.2);
+        let mut b = a;
+        b.temperature_surface += 2.5;
+        b.ocean_current_angle = Some(-3.1);
+        let a = EnvironmentFactors {
+            ocean_current_angle: Some(3.1),
+            ..a
+        };
+        let diff = diff_factors(&a, &b);
+        assert_eq!(diff.temperature_surface, 2.5);
+        // across the seam, by the short way
+        let wrapped = 2.0 * std::f64::consts::PI - 6.2;
+        assert!((diff.ocean_current_angle.unwrap() - wrapped).abs() < 1e-12);
+        assert_eq!(diff.diurnal_range, 0.0);
+        assert_eq!(diff.primitive_elevation, 0.0);
+
+        b.ocean_current_magnitude = None;
+        assert_eq!(diff_factors(&a, &b).ocean_current_magnitude, None);
+    }
+
+    #[test]
+    fn non_finite_values_get_the_fallback_color() {
+        let colormap = |value: f64| [value, 1.0 - value, 0.5];
+        let fallback = [1.0, 0.0, 1.0];
+        assert_eq!(safe_color(0.25, colormap, fallback), [0.25, 0.75, 0.5]);
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(safe_color(value, colormap, fallback), fallback);
+        }
+    }
+
+    /// Factors whose every field has a distinct value
+    fn distinct_factors() -> EnvironmentFactors {
+        EnvironmentFactors {
+            virtual_latitude: 0.1,
+            temperature_surface: 0.2,
+            diurnal_range: 0.3,
+            sea_level_pressure_normalized: Some(0.4),
+            atmosphere_pressure_normalized: Some(0.5),
+            atmosphere_current_angle: Some(0.6),
+            atmosphere_current_magnitude: Some(0.7),
+            atmosphere_current_speed: Some(0.8),
+            primitive_elevation_factors: PrimitiveElevationFactors {
+                shelf: 0.9,
+                persistence: ValueWithNormalized {
+                    value: 1.0,
+                    normalized: 1.1,
+                },
+                land_base: 1.2,
+                elevation: ValueWithNormalized {
+                    value: 1.3,
+                    normalized: 1.4,
+                },
+            },
+            ocean_current_angle: Some(1.5),
+            ocean_current_magnitude: Some(1.6),
+        }
+    }
+
+    #[test]
+    fn layers_route_to_their_fields() {
+        let factors = distinct_factors();
+        let scalars = [
+            (FactorLayer::VirtualLatitude, 0.1),
+            (FactorLayer::TemperatureSurface, 0.2),
+            (FactorLayer::DiurnalRange, 0.3),
+            (FactorLayer::SeaLevelPressureNormalized, 0.4),
+            (FactorLayer::AtmospherePressureNormalized, 0.5),
+            (FactorLayer::AtmosphereCurrentSpeed, 0.8),
+            (FactorLayer::PrimitiveShelf, 0.9),
+            (FactorLayer::PrimitivePersistence, 1.1),
+            (FactorLayer::PrimitiveLandBase, 1.2),
+            (FactorLayer::PrimitiveElevation, 1.4),
+        ];
+        for (layer, value) in scalars {
+            assert_eq!(factors.get_scalar(layer), Some(value), "{layer:?}");
+            assert_eq!(factors.get_vector(layer), None, "{layer:?}");
+        }
+        let vectors = [
+            (FactorLayer::AtmosphereCurrent, (0.6, 0.7)),
+            (FactorLayer::OceanCurrent, (1.5, 1.6)),
+        ];
+        for (layer, vector) in vectors {
+            assert_eq!(factors.get_vector(layer), Some(vector), "{layer:?}");
+            assert_eq!(factors.get_scalar(layer), None, "{layer:?}");
+        }
+    }
+
+    #[test]
+    fn unwrapped_angles_are_continuous_across_the_seam() {
+        use std::f64::consts::{PI, TAU};
+        let (cols, rows) = (20, 10);
+        // turning steadily by more than a full turn over the grid
+        let turning = |ix: usize, iy: usize| 0.3 * ix as f64 + 0.2 * iy as f64;
+        let angles = (0..rows)
+            .flat_map(|iy| (0..cols).map(move |ix| (turning(ix, iy) + PI).rem_euclid(TAU) - PI))
+            .collect::<Vec<_>>();
+        assert!(angles.windows(2).any(|a| (a[1] - a[0]).abs() > PI));
+
+        let unwrapped = angle_unwrap_grid(&angles, cols, rows);
+        for iy in 0..rows {
+            for ix in 0..cols {
+                let value = unwrapped[iy * cols + ix];
+                assert!((value - turning(ix, iy)).abs() < 1e-9, "{ix} {iy} {value}");
+            }
+        }
+
+        let mut with_gap = angles.clone();
+        with_gap[3] = f64::NAN;
+        let unwrapped = angle_unwrap_grid(&with_gap, cols, rows);
+        assert!(unwrapped[3].is_nan());
+        assert!((unwrapped[4] - turning(4, 0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn feature_array_follows_the_feature_names() {
+        let factors = distinct_factors();
+        let features = factors.to_feature_array();
+        assert_eq!(features.len(), FEATURE_NAMES.len());
+        let expected = [
+            ("elevation_normalized", 1.4),
+            ("elevation", 1.3),
+            ("primitive_shelf", 0.9),
+            ("primitive_persistence_normalized", 1.1),
+            ("primitive_land_base", 1.2),
+            ("virtual_latitude", 0.1),
+            ("temperature_surface", 0.2),
+            ("diurnal_range", 0.3),
+            ("sea_level_pressure_normalized", 0.4),
+            ("atmosphere_pressure_normalized", 0.5),
+            ("atmosphere_current_x", 0.7 * 0.6_f64.cos()),
+            ("atmosphere_current_y", 0.7 * 0.6_f64.sin()),
+            ("atmosphere_current_speed", 0.8),
+            ("ocean_current_x", 1.6 * 1.5_f64.cos()),
+            ("ocean_current_y", 1.6 * 1.5_f64.sin()),
+        ];
+        for ((name, value), (feature_name, feature)) in
+            expected.iter().zip(FEATURE_NAMES.iter().zip(features))
+        {
+            assert_eq!(name, feature_name);
+            assert_eq!(feature, *value as f32, "{name}");
+        }
+    }
+
+    #[test]
+    fn tolerance_accepts_small_and_rejects_large_perturbations() {
+        let a = distinct_factors();
+        let tol = FactorTolerance::uniform(0.01);
+        assert!(factors_approx_eq(&a, &a, &FactorTolerance::default()));
+
+        let mut b = a;
+        b.temperature_surface += 0.005;
+        assert!(factors_approx_eq(&a, &b, &tol));
+        b.temperature_surface += 0.01;
+        assert!(!factors_approx_eq(&a, &b, &tol));
+        // other fields keep their own tolerance
+        assert!(factors_approx_eq(
+            &a,
+            &b,
+            &FactorTolerance {
+                temperature_surface: 0.02,
+                ..tol
+            }
+        ));
+
+        // angles by the short way across the seam
+        let a = EnvironmentFactors {
+            ocean_current_angle: Some(std::f64::consts::PI - 0.002),
+            ..a
+        };
+        let mut b = EnvironmentFactors {
+            ocean_current_angle: Some(-std::f64::consts::PI + 0.003),
+            ..a
+        };
+        assert!(factors_approx_eq(&a, &b, &tol));
+        b.ocean_current_magnitude = None;
+        assert!(!factors_approx_eq(&a, &b, &tol));
+    }
+
+    /// distinct_factors at the normalized elevation `normalized`
+    fn factors_at_elevation(normalized: f64) -> EnvironmentFactors {
+        let mut factors = distinct_factors();
+        factors.primitive_elevation_factors.elevation.normalized = normalized;
+        factors
+    }
+
+    #[test]
+    fn landness_rises_from_the_deep_ocean_to_the_peaks() {
+        let (min, max) = ELEVATION_NORMALIZED_LIMITS;
+        for sea_level in [0.0, 0.3] {
+            let landness = |elevation: f64| factors_at_elevation(elevation).landness(sea_level);
+            assert!(landness(min) < 1e-12);
+            assert!((landness(sea_level) - 0.5).abs() < 1e-12);
+            assert!(landness(max) > 1.0 - 1e-12);
+
+            let samples = (0..=100)
+                .map(|i| landness(min + (max - min) * i as f64 / 100.0))
+                .collect::<Vec<_>>();
+            assert!(samples.windows(2).all(|l| l[0] <= l[1]));
+        }
+    }
+
+    #[test]
+    fn water_class_separates_the_shelf_from_the_deep_ocean() {
+        let shelf_depth = 0.1;
+        for sea_level in [0.0, 0.2] {
+            let class = |elevation: f64| {
+                factors_at_elevation(elevation).water_class(sea_level, shelf_depth)
+            };
+            assert_eq!(class(sea_level + 0.01), WaterClass::Land);
+            assert_eq!(class(sea_level), WaterClass::Land);
+            assert_eq!(class(sea_level - 0.01), WaterClass::Shelf);
+            assert_eq!(class(sea_level - shelf_depth + 0.01), WaterClass::Shelf);
+            assert_eq!(class(sea_level - shelf_depth - 0.01), WaterClass::DeepOcean);
+            assert_eq!(class(-1.0), WaterClass::DeepOcean);
+        }
+    }
+
+    #[test]
+    fn remapping_to_the_original_range_gives_the_value() {
+        let range = ValueRange {
+            min: -3000.0,
+            max: 5000.0,
+        };
+        let display = ValueRange { min: 0.0, max: 1.0 };
+        for normalized in [0.0, 0.25, 0.6, 1.0] {
+            let value = ValueWithNormalized::from_normalized(normalized, range);
+            assert!((value.remap(range) - value.value).abs() < 1e-9);
+            assert!((value.remap(display) - normalized).abs() < 1e-12);
+            assert_eq!(remap_normalized(normalized, range), value.value);
+        }
+        for normalized in [-1.0, -0.3, 0.0, 0.8, 1.0] {
+            let value = ValueWithNormalized::from_signed_normalized(normalized, range);
+            assert!((value.remap_signed(range) - value.value).abs() < 1e-9);
+            let inverse = ValueWithNormalized::from_signed_value(value.value, range);
+            assert!((inverse.normalized - normalized).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn currents_at_or_below_the_threshold_are_not_significant() {
+        let threshold = 0.3;
+        let factors = |magnitude: Option<f64>| EnvironmentFactors {
+            ocean_current_magnitude: magnitude,
+            atmosphere_current_magnitude: magnitude,
+            ..distinct_factors()
+        };
+        for (magnitude, significant) in [
+            (Some(0.0), false),
+            (Some(0.1), false),
+            (Some(-0.2), false),
+            (Some(threshold), false),
+            (Some(0.31), true),
+            (Some(-0.5), true),
+            (None, false),
+        ] {
+            let factors = factors(magnitude);
+            assert_eq!(
+                factors.has_significant_ocean_current(threshold),
+                significant,
+                "{magnitude:?}"
+            );
+            assert_eq!(
+                factors.has_significant_atmosphere_current(threshold),
+                significant,
+                "{magnitude:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Hexagonal grid for sampling at hex cell centers
+
+use crate::Region;
+
+/// Orientation of the hexagons
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HexOrientation {
+    /// A vertex points toward +y; the rows of constant r run along the x axis
+    #[default]
+    PointyTop,
+    /// An edge faces +y; the columns of constant q run along the y axis
+    FlatTop,
+}
+
+/// Axial coordinates of a hex cell.
+///
+/// The cell (0, 0) is centered at the origin of the world coordinates, and the centers
+/// of adjacent cells are `hex_size` apart:
+/// - PointyTop: center = hex_size * (q + r / 2, r * sqrt(3) / 2)
+/// - FlatTop: center = hex_size * (q * sqrt(3) / 2, r + q / 2)
+///
+/// The neighbors of (q, r) are (q +- 1, r), (q, r +- 1), (q + 1, r - 1) and (q - 1, r + 1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HexCoord {
+    pub q: i32,
+    pub r: i32,
+}
+
+impl HexCoord {
+    pub fn new(q: i32, r: i32) -> Self {
+        Self { q, r }
+    }
+
+    /// Center of the cell in the world coordinates
+    pub fn center(&self, hex_size: f64, orientation: HexOrientation) -> (f64, f64) {
+        let (q, r) = (self.q as f64, self.r as f64);
+        let row_spacing = hex_size * 3.0_f64.sqrt() / 2.0;
+        match orientation {
+            HexOrientation::PointyTop => (hex_size * (q + r / 2.0), row_spacing * r),
+            HexOrientation::FlatTop => (row_spacing * q, hex_size * (r + q / 2.0)),
+        }
+    }
+
+    /// Cells whose centers lie in `region` (edges included), row by row
+    /// (by r then q for PointyTop, by q then r for FlatTop)
+    pub fn cells_in(region: Region, hex_size: f64, orientation: HexOrientation) -> Vec<HexCoord> {
+        let row_spacing = hex_size * 3.0_f64.sqrt() / 2.0;
+        // (across the rows, along the rows) in the world coordinates
+        let ((row_min, row_max), (along_min, along_max)) = match orientation {
+            HexOrientation::PointyTop => {
+                ((region.min_y, region.max_y), (region.min_x, region.max_x))
+            }
+            HexOrientation::FlatTop => ((region.min_x, region.max_x), (region.min_y, region.max_y)),
+        };
+        let mut cells = vec![];
+        let rows = (row_min / row_spacing).ceil() as i32..=(row_max / row_spacing).floor() as i32;
+        for row in rows {
+            let shift = row as f64 / 2.0;
+            let first = (along_min / hex_size - shift).ceil() as i32;
+            let last = (along_max / hex_size - shift).floor() as i32;
+            cells.extend((first..=last).map(|along| match orientation {
+                HexOrientation::PointyTop => HexCoord::new(along, row),
+                HexOrientation::FlatTop => HexCoord::new(row, along),
+            }));
+        }
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIENTATIONS: [HexOrientation; 2] = [HexOrientation::PointyTop, HexOrientation::FlatTop];
+
+    #[test]
+    fn adjacent_centers_are_hex_size_apart() {
+        let hex_size = 0.3;
+        let neighbors = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, -1), (-1, 1)];
+        for orientation in ORIENTATIONS {
+            for cell in [
+                HexCoord::new(0, 0),
+                HexCoord::new(3, -2),
+                HexCoord::new(-5, 7),
+            ] {
+                let (x, y) = cell.center(hex_size, orientation);
+                for (dq, dr) in neighbors {
+                    let (nx, ny) =
+                        HexCoord::new(cell.q + dq, cell.r + dr).center(hex_size, orientation);
+                    assert!(((nx - x).hypot(ny - y) - hex_size).abs() < 1e-12);
+                }
+                // the next ring is farther
+                let (fx, fy) = HexCoord::new(cell.q + 1, cell.r + 1).center(hex_size, orientation);
+                assert!((fx - x).hypot(fy - y) > hex_size * 1.5);
+            }
+        }
+    }
+
+    #[test]
+    fn cells_in_are_the_cells_centered_in_the_region() {
+        let region = Region {
+            min_x: -1.3,
+            min_y: -0.7,
+            max_x: 2.1,
+            max_y: 1.6,
+        };
+        let hex_size = 0.25;
+        for orientation in ORIENTATIONS {
+            let mut expected = vec![];
+            for q in -40..=40 {
+                for r in -40..=40 {
+                    let cell = HexCoord::new(q, r);
+                    let (x, y) = cell.center(hex_size, orientation);
+                    if (region.min_x..=region.max_x).contains(&x)
+                        && (region.min_y..=region.max_y).contains(&y)
+                    {
+                        expected.push(cell);
+                    }
+                }
+            }
+            let mut cells = HexCoord::cells_in(region, hex_size, orientation);
+            // about the area of the region over the area of a cell
+            let cell_area = hex_size * hex_size * 3.0_f64.sqrt() / 2.0;
+            let area = (region.max_x - region.min_x) * (region.max_y - region.min_y);
+            assert!((cells.len() as f64 - area / cell_area).abs() < 0.15 * area / cell_area);
+
+            let key = |cell: &HexCoord| (cell.q, cell.r);
+            cells.sort_by_key(key);
+            expected.sort_by_key(key);
+            assert_eq!(cells, expected, "{orientation:?}");
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Isolines on sampled grids and queries over polylines
+
+use std::collections::HashMap;
+
+use crate::Region;
+
+/// Grid of buckets over polylines (e.g. the coastline) answering repeated nearest-point
+/// queries without searching every segment
+pub struct CoastIndex {
+    cell_size: f64,
+    segments: Vec<((f64, f64), (f64, f64))>,
+    /// cell -> segments whose bounding box overlaps the cell
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    /// (min, max) of the occupied cells
+    cell_bounds: ((i64, i64), (i64, i64)),
+}
+
+impl CoastIndex {
+    /// Indexes the segments of `lines` (e.g. from coastline) on buckets of `cell_size`
+    /// (about the typical query distance works well)
+    pub fn new(lines: &[Vec<(f64, f64)>], cell_size: f64) -> Self {
+        let segments = lines
+            .iter()
+            .flat_map(|line| match line.len() {
+                1 => vec![(line[0], line[0])],
+                _ => line.windows(2).map(|pair| (pair[0], pair[1])).collect(),
+            })
+            .collect::<Vec<_>>();
+        let cell = |(x, y): (f64, f64)| {
+            (
+                (x / cell_size).floor() as i64,
+                (y / cell_size).floor() as i64,
+            )
+        };
+        let mut cells: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+        let mut cell_bounds = ((i64::MAX, i64::MAX), (i64::MIN, i64::MIN));
+        for (i, &(a, b)) in segments.iter().enumerate() {
+            let (min_x, min_y) = cell((a.0.min(b.0), a.1.min(b.1)));
+            let (max_x, max_y) = cell((a.0.max(b.0), a.1.max(b.1)));
+            for cy in min_y..=max_y {
+                for cx in min_x..=max_x {
+                    cells.entry((cx, cy)).or_default().push(i);
+                }
+            }
+            cell_bounds = (
+                (cell_bounds.0 .0.min(min_x), cell_bounds.0 .1.min(min_y)),
+                (cell_bounds.1 .0.max(max_x), cell_bounds.1 .1.max(max_y)),
+            );
+        }
+        Self {
+            cell_size,
+            segments,
+            cells,
+            cell_bounds,
+        }
+    }
+
+    /// Nearest point on the indexed polylines to (x, y), with its distance
+    /// (None if nothing is indexed)
+    pub fn nearest(&self, x: f64, y: f64) -> Option<((f64, f64), f64)> {
+        if self.segments.is_empty() {
+            return None;
+        }
+        let (cx, cy) = (
+            (x / self.cell_size).floor() as i64,
+            (y / self.cell_size).floor() as i64,
+        );
+        let ((min_x, min_y), (max_x, max_y)) = self.cell_bounds;
+        // rings of cells around (cx, cy) between the nearest and the farthest occupied cell
+        let first_ring = (min_x - cx)
+            .max(cx - max_x)
+            .max(min_y - cy)
+            .max(cy - max_y)
+            .max(0);
+        let last_ring = (cx - min_x).max(max_x - cx).max(cy - min_y).max(max_y - cy);
+
+        let mut best: Option<((f64, f64), f64)> = None;
+        for ring in first_ring..=last_ring {
+            // every point of the cells of this ring is at least (ring - 1) cells away
+            if best.is_some_and(|(_, distance)| distance <= (ring - 1) as f64 * self.cell_size) {
+                break;
+            }
+            let ring_cells = (cy - ring..=cy + ring)
+                .flat_map(|ny| (cx - ring..=cx + ring).map(move |nx| (nx, ny)))
+                .filter(|&(nx, ny)| (nx - cx).abs() == ring || (ny - cy).abs() == ring);
+            for key in ring_cells {
+                let Some(indices) = self.cells.get(&key) else {
+                    continue;
+                };
+                for &i in indices {
+                    let (a, b) = self.segments[i];
+                    let point = closest_point_on_segment(a, b, (x, y));
+                    let distance = (point.0 - x).hypot(point.1 - y);
+                    if best.is_none_or(|(_, best_distance)| distance < best_distance) {
+                        best = Some((point, distance));
+                    }
+                }
+            }
+        }
+        best
+    }
+}
+
+/// Point of the segment from `a` to `b` nearest to `p`
+fn closest_point_on_segment(a: (f64, f64), b: (f64, f64), p: (f64, f64)) -> (f64, f64) {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    if length_squared == 0.0 {
+        return a;
+    }
+    let t = (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length_squared).clamp(0.0, 1.0);
+    (a.0 + t * dx, a.1 + t * dy)
+}
+
+/// marching_squares on the grid sampling `region` every `resolution`,
+/// with the polylines converted into the world coordinates
+pub(crate) fn world_isolines(
+    values: &[Option<f64>],
+    cols: usize,
+    rows: usize,
+    threshold: f64,
+    region: Region,
+    resolution: f64,
+) -> Vec<Vec<(f64, f64)>> {
+    marching_squares(values, cols, rows, threshold)
+        .into_iter()
+        .map(|line| {
+            line.into_iter()
+                .map(|(gx, gy)| {
+                    (
+                        region.min_x + gx * resolution,
+                        region.min_y + gy * resolution,
+                    )
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Grid edge crossed by an isoline: (is_vertical, ix, iy)
+type GridEdge = (bool, usize, usize);
+
+/// Traces the isolines of `threshold` on a row-major grid of `cols` x `rows` values.
+/// Returns polylines in grid coordinates (closed ones end with their first point).
+fn marching_squares(
+    values: &[Option<f64>],
+    cols: usize,
+    rows: usize,
+    threshold: f64,
+) -> Vec<Vec<(f64, f64)>> {
+    let mut points: HashMap<GridEdge, (f64, f64)> = HashMap::new();
+    let mut segments: Vec<(GridEdge, GridEdge)> = Vec::new();
+
+    let crossing = |a: f64, b: f64| (threshold - a) / (b - a);
+
+    for iy in 0..rows.saturating_sub(1) {
+        for ix in 0..cols.saturating_sub(1) {
+            let corners = [
+                values[iy * cols + ix],
+                values[iy * cols + ix + 1],
+                values[(iy + 1) * cols + ix + 1],
+                values[(iy + 1) * cols + ix],
+            ];
+            let [Some(v00), Some(v10), Some(v11), Some(v01)] = corners else {
+                continue;
+            };
+            let above = [v00, v10, v11, v01].map(|v| v >= threshold);
+
+            let bottom = (false, ix, iy);
+            let right = (true, ix + 1, iy);
+            let top = (false, ix, iy + 1);
+            let left = (true, ix, iy);
+            let mut crossed = Vec::with_capacity(4);
+            if above[0] != above[1] {
+                points.insert(bottom, (ix as f64 + crossing(v00, v10), iy as f64));
+                crossed.push(bottom);
+            }
+            if above[1] != above[2] {
+                points.insert(right, ((ix + 1) as f64, iy as f64 + crossing(v10, v11)));
+                crossed.push(right);
+            }
+            if above[2] != above[3] {
+                points.insert(top, (ix as f64 + crossing(v01, v11), (iy + 1) as f64));
+                crossed.push(top);
+            }
+            if above[3] != above[0] {
+                points.insert(left, (ix as f64, iy as f64 + crossing(v00, v01)));
+                crossed.push(left);
+            }
+
+            if crossed.len() == 2 {
+                segments.push((crossed[0], crossed[1]));
+            } else if crossed.len() == 4 {
+                // saddle: resolve the ambiguity by the value at the center of the cell,
+                // cutting off the corners (00, 10, 11, 01) that differ from it
+                let center_above = (v00 + v10 + v11 + v01) * 0.25 >= threshold;
+                let cut_segments = [(left, bottom), (bottom, right), (right, top), (top, left)];
+                for (corner, segment) in cut_segments.into_iter().enumerate() {
+                    if above[corner] != center_above {
+                        segments.push(segment);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut adjacency: HashMap<GridEdge, Vec<usize>> = HashMap::new();
+    for (i, (a, b)) in segments.iter().enumerate() {
+        adjacency.entry(*a).or_default().push(i);
+        adjacency.entry(*b).or_default().push(i);
+    }
+
+    let mut visited = vec![false; segments.len()];
+    let trace = |start: GridEdge, visited: &mut [bool]| {
+        let mut line = vec![points[&start]];
+        let mut current = start;
+        while let Some(&i) = adjacency[&current].iter().find(|&&i| !visited[i]) {
+            visited[i] = true;
+            let (a, b) = segments[i];
+            current = if a == current { b } else { a };
+            line.push(points[&current]);
+        }
+        line
+    };
+
+    let mut lines = Vec::new();
+    // open lines start from the ends (edges with a single segment)
+    let mut ends = adjacency
+        .iter()
+        .filter(|(_, segments)| segments.len() == 1)
+        .map(|(edge, _)| *edge)
+        .collect::<Vec<_>>();
+    ends.sort();
+    for end in ends {
+        if adjacency[&end].iter().any(|&i| !visited[i]) {
+            lines.push(trace(end, &mut visited));
+        }
+    }
+    // the rest are closed loops
+    for i in 0..segments.len() {
+        if !visited[i] {
+            lines.push(trace(segments[i].0, &mut visited));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Nearest point on `lines` searching every segment
+    fn brute_force_nearest(lines: &[Vec<(f64, f64)>], x: f64, y: f64) -> ((f64, f64), f64) {
+        lines
+            .iter()
+            .flat_map(|line| line.windows(2))
+            .map(|pair| {
+                let point = closest_point_on_segment(pair[0], pair[1], (x, y));
+                (point, (point.0 - x).hypot(point.1 - y))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+    }
+
+    #[test]
+    fn coast_index_agrees_with_the_brute_force_search() {
+        // a closed island and a wiggly open coast
+        let island = (0..=40)
+            .map(|i| {
+                let angle = i as f64 / 40.0 * std::f64::consts::TAU;
+                (0.3 * angle.cos() - 0.4, 0.2 * angle.sin())
+            })
+            .collect::<Vec<_>>();
+        let coast = (0..=60)
+            .map(|i| {
+                let x = i as f64 * 0.02;
+                (x, 0.3 + 0.05 * (x * 17.0).sin())
+            })
+            .collect::<Vec<_>>();
+        let lines = vec![island, coast];
+
+        for cell_size in [0.05, 0.2, 1.0] {
+            let index = CoastIndex::new(&lines, cell_size);
+            for iy in -10..=10 {
+                for ix in -15..=15 {
+                    let (x, y) = (ix as f64 * 0.13, iy as f64 * 0.11);
+                    let (_, distance) = index.nearest(x, y).unwrap();
+                    let (_, expected) = brute_force_nearest(&lines, x, y);
+                    assert!((distance - expected).abs() < 1e-12, "{x} {y}");
+                }
+            }
+        }
+        assert!(CoastIndex::new(&[], 0.1).nearest(0.0, 0.0).is_none());
+    }
+}
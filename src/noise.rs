@@ -0,0 +1,115 @@
+//! Noise sources and the accumulation of their octaves
+
+use libnoise::{Generator, Simplex};
+
+/// Noise channels of [ReferenceEnvironmentProvider](crate::ReferenceEnvironmentProvider)
+/// (indices of its noise sources)
+pub(crate) const NOISE_PRIMITIVE_CONTINENT: usize = 0;
+
+pub(crate) const NOISE_PRIMITIVE_PERSISTENCE: usize = 1;
+
+pub(crate) const NOISE_PRIMITIVE_LAND: usize = 2;
+
+pub(crate) const NOISE_OCEAN_CURRENT: usize = 3;
+
+pub(crate) const NOISE_ATMOSPHERE_PRESSURE: usize = 4;
+
+pub(crate) const NOISE_FJORD: usize = 5;
+
+pub(crate) const NOISE_OCEAN_CURRENT_DETAIL: usize = 6;
+
+pub(crate) const NOISE_TEMPERATURE: usize = 7;
+
+pub(crate) const NOISE_END: usize = 10;
+
+/// 2D noise sampled by [ReferenceEnvironmentProvider](crate::ReferenceEnvironmentProvider)
+/// (expected in [-1.0, 1.0])
+pub trait NoiseSource {
+    fn sample(&self, x: f64, y: f64) -> f64;
+}
+
+impl NoiseSource for Simplex<2> {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        Generator::sample(self, [x, y])
+    }
+}
+
+/// Analytic noise source: offset + gradient_x * x + gradient_y * y
+/// (makes the results of [ReferenceEnvironmentProvider](crate::ReferenceEnvironmentProvider)
+/// predictable in the tests)
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct PlaneNoise {
+    pub offset: f64,
+    pub gradient_x: f64,
+    pub gradient_y: f64,
+}
+
+#[cfg(test)]
+impl NoiseSource for PlaneNoise {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        self.offset + self.gradient_x * x + self.gradient_y * y
+    }
+}
+
+/// SplitMix64 mixing of `state` (well separated outputs for adjacent inputs;
+/// feeding it a counter advanced by 0x9e3779b97f4a7c15 gives the SplitMix64 generator)
+pub(crate) fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Number of octaves sampled out of `octaves` at the level of detail `lod`
+/// (the `lod` finest octaves are dropped, keeping at least the coarsest one)
+pub(crate) fn lod_octaves(octaves: usize, lod: u32) -> usize {
+    octaves.saturating_sub(lod as usize).max(1)
+}
+
+/// persistence^k for the octave k of `octaves`
+pub(crate) fn geometric_amplitudes(octaves: usize, persistence: f64) -> impl Iterator<Item = f64> {
+    std::iter::successors(Some(1.0), move |amplitude| Some(amplitude * persistence)).take(octaves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plane_noise_is_linear() {
+        let noise = PlaneNoise {
+            offset: 0.5,
+            gradient_x: 2.0,
+            gradient_y: -1.0,
+        };
+        assert_eq!(noise.sample(0.0, 0.0), 0.5);
+        assert_eq!(noise.sample(1.0, 2.0), 0.5);
+        assert_eq!(noise.sample(-0.25, 0.0), 0.0);
+    }
+
+    #[test]
+    fn splitmix64_scatters_adjacent_inputs() {
+        let outputs = (0..NOISE_END as u64).map(splitmix64).collect::<Vec<_>>();
+        for (i, a) in outputs.iter().enumerate() {
+            for b in &outputs[i + 1..] {
+                assert!((a ^ b).count_ones() > 8, "{a:x} and {b:x} are too close");
+            }
+        }
+    }
+
+    #[test]
+    fn lod_octaves_keeps_the_coarsest_octave() {
+        assert_eq!(lod_octaves(8, 0), 8);
+        assert_eq!(lod_octaves(8, 3), 5);
+        assert_eq!(lod_octaves(8, 8), 1);
+        assert_eq!(lod_octaves(8, 100), 1);
+    }
+
+    #[test]
+    fn geometric_amplitudes_decay_by_persistence() {
+        let amplitudes = geometric_amplitudes(4, 0.5).collect::<Vec<_>>();
+        assert_eq!(amplitudes, vec![1.0, 0.5, 0.25, 0.125]);
+        assert_eq!(geometric_amplitudes(0, 0.5).count(), 0);
+    }
+}
@@ -0,0 +1,892 @@
+//! Parameters of [crate::ReferenceEnvironmentProvider] and their serializable counterpart
+
+use crate::{ValueRange, ValueWithNormalized};
+
+/// Upper bound of the persistence actually used for the land noise.
+/// The amplitude of the octave k is persistence^k, so the coarse octaves only dominate
+/// (and the land keeps its large-scale shape) while persistence < 1.0;
+/// at 1.0 and above the finest octaves are as strong as the continents.
+pub const MAX_STABLE_PERSISTENCE: f64 = 0.95;
+
+/// Smallest primitive_land_power accepted by validate.
+/// x^0 is 1.0 everywhere (flattening all the land to the top of the range),
+/// and negative powers diverge toward the coast.
+pub const MIN_LAND_POWER: f64 = 0.05;
+
+/// Maps a human "roughness" [0.0, 1.0] to a persistence in [0.0, MAX_STABLE_PERSISTENCE]
+/// (e.g. for primitive_persistence_range)
+pub fn persistence_from_roughness(roughness: f64) -> f64 {
+    roughness.clamp(0.0, 1.0) * MAX_STABLE_PERSISTENCE
+}
+
+/// Number of octaves of the land noise (unless primitive_land_octave_amplitudes is set)
+pub const PRIMITIVE_LAND_OCTAVES: usize = 8;
+
+/// Hard limits (min, max) of elevation.normalized, to which the elevation is clamped
+/// (see ReferenceEnvironmentProvider::elevation_normalized_bounds for the actual range)
+pub const ELEVATION_NORMALIZED_LIMITS: (f64, f64) = (-1.0, 1.0);
+
+/// Invalid field of [ReferenceEnvironmentParameters] or of its config, found by `validate`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterError {
+    /// Name of the field
+    pub field: &'static str,
+    /// Why its value is rejected
+    pub reason: String,
+}
+
+impl std::fmt::Display for ParameterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid {}: {}", self.field, self.reason)
+    }
+}
+
+impl std::error::Error for ParameterError {}
+
+fn validate_land_power(power: f64) -> Result<(), ParameterError> {
+    if power.is_finite() && power >= MIN_LAND_POWER {
+        return Ok(());
+    }
+    Err(ParameterError {
+        field: "primitive_land_power",
+        reason: format!("must be finite and at least {MIN_LAND_POWER}, got {power}"),
+    })
+}
+
+fn validate_octave_amplitudes(amplitudes: Option<&[f64]>) -> Result<(), ParameterError> {
+    let Some(amplitudes) = amplitudes else {
+        return Ok(());
+    };
+    let error = |reason: String| ParameterError {
+        field: "primitive_land_octave_amplitudes",
+        reason,
+    };
+    if let Some(amplitude) = amplitudes
+        .iter()
+        .find(|amplitude| !(amplitude.is_finite() && **amplitude >= 0.0))
+    {
+        return Err(error(format!(
+            "amplitudes must be finite and non-negative, got {amplitude}"
+        )));
+    }
+    // the land noise is normalized by the sum
+    if amplitudes.iter().sum::<f64>() <= 0.0 {
+        return Err(error(format!(
+            "the sum of the amplitudes must be positive, got {amplitudes:?}"
+        )));
+    }
+    Ok(())
+}
+
+pub struct ReferenceEnvironmentParameters {
+    pub primitive_shelf_scale: f64,
+    pub primitive_shelf_power: f64,
+    pub primitive_shelf_depth: f64,
+    /// Depth (normalized) of the flat abyssal floor; ocean deeper than this is flattened
+    pub primitive_abyssal_depth: Option<f64>,
+
+    /// Acceptable range of persistence
+    /// (the land noise clamps it into [0.0, MAX_STABLE_PERSISTENCE])
+    pub primitive_persistence_range: ValueRange,
+    pub primitive_persistence_scale: f64,
+
+    pub primitive_land_scale: f64,
+    /// Power applied to the positive normalized elevation
+    /// (finite, MIN_LAND_POWER or above; < 1.0 raises and > 1.0 lowers the lowlands)
+    pub primitive_land_power: f64,
+    /// Amplitude of each octave of the land noise, from the coarsest
+    /// (None: persistence^k for the octave k of PRIMITIVE_LAND_OCTAVES, where persistence
+    /// is primitive_persistence; when set, the number of octaves is the length of the slice
+    /// and primitive_persistence no longer affects the land. The amplitudes must be finite
+    /// and non-negative, with a positive sum; see validate)
+    pub primitive_land_octave_amplitudes: Option<Vec<f64>>,
+    /// Scale of the land noise under the ocean, blended with primitive_land_scale by the
+    /// continentalness (|n|^primitive_shelf_power * sign(n) * 0.5 + 0.5 of the continent
+    /// noise n), e.g. larger for coarser ocean floor features (None: primitive_land_scale)
+    pub ocean_floor_scale: Option<f64>,
+    /// [0.0, 1.0] -> [0.0, 1.0], applied to the positive normalized elevation instead of
+    /// powf(primitive_land_power) when set (for plateaus, cliffs, etc.)
+    pub land_shaping_fn: Option<Box<dyn Fn(f64) -> f64 + Send + Sync>>,
+    /// Half width (normalized) of the blend between the shelf and the shaped land around
+    /// the sea level (0.0: the land shaping is switched on sharply at the sea level)
+    pub shelf_blend_smoothness: f64,
+    /// Distance from the edge of the valid area (valid_fn) over which the elevation is
+    /// lowered smoothly toward -primitive_shelf_depth, surrounding the world with ocean
+    /// (0.0: disabled; see boundary_proximity)
+    pub boundary_land_falloff: f64,
+    /// Factor of the positive normalized elevation (the land height)
+    pub land_elevation_scale: f64,
+    /// Factor of the negative normalized elevation (the ocean depth), e.g. larger than
+    /// land_elevation_scale for oceans deeper than the land is high
+    pub ocean_depth_scale: f64,
+    /// Depth (normalized) below the sea level down to which the submerged elevation is
+    /// classified as the shelf (see EnvironmentFactors::water_class)
+    pub shelf_class_depth: f64,
+
+    /// Real elevation range (m)
+    pub primitive_elevation_range: ValueRange,
+    /// Offset (m) added to the generated elevation.value, raising (or lowering) everything
+    /// uniformly relative to the sea level; the result is clamped to primitive_elevation_range
+    /// and the normalized elevation is recomputed from it
+    pub elevation_bias: f64,
+    /// Real length (m) of a unit of the x / y coordinates
+    pub coordinate_meters_per_unit: f64,
+
+    /// Strength of the coastline warping by the slope of the land (0.0: disabled)
+    /// (the continent is displaced by fjord_strength * slope * fjord_scale * noise,
+    /// so flat coasts stay smooth while mountainous coasts become jagged like fjords)
+    pub fjord_strength: f64,
+    /// Scale of the noise warping the coastline
+    pub fjord_scale: f64,
+
+    pub ocean_current_scale: f64,
+    /// Scale of the second ocean current channel (smaller eddies)
+    pub ocean_current_detail_scale: f64,
+    /// Weight of the gradient of the second ocean current channel (0.0: disabled)
+    pub ocean_current_detail_weight: f64,
+    /// Max distance of ocean current effect (particulary for temperature)
+    pub ocean_current_elevation_effect_distance: f64,
+    /// Number of steps in which the temperature is carried along the ocean current over
+    /// ocean_current_elevation_effect_distance: the temperature is taken from the point
+    /// reached by tracing the current upstream, following the current at each step
+    /// (0 or 1: a single shift against the local current)
+    pub ocean_heat_transport_steps: u32,
+    /// Global drift (x, y) added to every ocean current vector
+    /// (ocean_current_magnitude * (cos, sin) of ocean_current_angle),
+    /// scaled like the local current by being in the ocean and by cos(virtual_latitude)
+    pub global_ocean_drift: Option<(f64, f64)>,
+    /// Output of ocean_current_magnitude (the heat transport always uses the full current)
+    pub ocean_current_output: CurrentOutput,
+    /// Bound of |ocean_current_magnitude| in the output, capping the outliers
+    /// (None: unbounded; applied before ocean_current_output)
+    pub current_magnitude_clamp: Option<f64>,
+    /// Magnitude of a current (ocean or atmosphere) at or below which renderers may skip it
+    /// (see EnvironmentFactors::has_significant_ocean_current; not applied to the factors)
+    pub current_ignorable_magnitude: f64,
+
+    /// (x, y) -> virtual_latitude [-PI/2, PI/2]
+    pub virtual_latitude_fn: Box<dyn Fn(f64, f64) -> f64 + Send + Sync>,
+    /// (x, y) -> valid or not
+    pub valid_fn: Box<dyn Fn(f64, f64) -> bool + Send + Sync>,
+    /// (x, y, elevation) -> valid or not, checked in addition to valid_fn once the elevation
+    /// is known, e.g. `Box::new(|_, _, elevation| elevation.normalized > -0.8)` to exclude the
+    /// deep ocean (the samples of gradient calculation only use valid_fn)
+    pub valid_elevation_fn: Option<ValidElevationFn>,
+    /// latitude or insolation (see temperature_input) -> temperature_surface (degree)
+    pub temperature_surface_fn: Box<dyn Fn(f64) -> f64 + Send + Sync>,
+    /// Input given to temperature_surface_fn
+    pub temperature_input: TemperatureInput,
+    /// Built-in profile used instead of temperature_surface_fn (and temperature_input) when set
+    pub temperature_profile: Option<TemperatureProfile>,
+    /// Range the final temperature_surface is clamped to (None: whatever
+    /// temperature_surface_fn returns, which is not bounded by itself)
+    pub temperature_clamp: Option<ValueRange>,
+    /// Soft lower bound of temperature_surface (degree), applied before temperature_clamp:
+    /// the temperature t is replaced by floor + softness * ln(1 + exp((t - floor) / softness))
+    /// (softness: temperature_floor_softness), which approaches the floor smoothly from above
+    /// for low t and stays close to t well above the floor
+    pub temperature_floor: Option<f64>,
+    /// Width (degree) of the transition of temperature_floor
+    pub temperature_floor_softness: f64,
+    /// Proportion of the micro-climate perturbation of temperature_surface (0.0: disabled):
+    /// noise * temperature_noise_prop * temperature_noise_band is added to the output of
+    /// temperature_surface_fn (before temperature_floor and temperature_clamp)
+    pub temperature_noise_prop: f64,
+    /// Amplitude (degree) of the micro-climate perturbation at temperature_noise_prop 1.0
+    pub temperature_noise_band: f64,
+    /// Scale of the micro-climate noise
+    pub temperature_noise_scale: f64,
+
+    /// Diurnal temperature range over the ocean (degree)
+    pub diurnal_range_base: f64,
+    /// Additional diurnal temperature range of the driest (most continental) land (degree)
+    pub diurnal_range_modulation: f64,
+
+    /// Offset (x, y) added to the coordinates before every noise sample,
+    /// panning the procedural content without moving the coordinate system
+    /// (virtual_latitude_fn and valid_fn still receive the original coordinates)
+    pub noise_origin: (f64, f64),
+    /// Rotation (radian) of the noise domain added per octave, decorrelating the octaves
+    /// of every noise layer (0.0: all octaves share the same orientation)
+    pub octave_rotation: f64,
+    /// Rotation (radian) of the whole noise domain, turning the orientation of the world
+    /// coherently and deterministically (all noise layers and octaves alike)
+    pub domain_rotation: f64,
+    /// Scale the x coordinate of the noise domain by cos(virtual_latitude), as the meridians
+    /// converge toward the poles, so that the features are stretched in x at high latitude
+    /// and a later spherical mapping does not compress them
+    pub polar_convergence: bool,
+    /// Lower bound of the x scale of polar_convergence (avoiding the singularity at the poles)
+    pub polar_convergence_min_scale: f64,
+
+    /// Treatment of the samples of the gradient search (field_gradient_at) outside the valid
+    /// area (the ocean and atmosphere currents are derived from infinitesimal differences of
+    /// the noise at the point itself and are not affected)
+    pub edge_mode: EdgeMode,
+    /// Period of the world in x, used by EdgeMode::Wrap: the world spans
+    /// [x_wrap_min, x_wrap_min + x_wrap_period) and x outside is wrapped into it
+    pub x_wrap_period: f64,
+    /// Western bound of the world wrapped by EdgeMode::Wrap
+    pub x_wrap_min: f64,
+
+    /// Number of samples of the gradient search (field_gradient_at)
+    pub gradient_sample_num: i32,
+    /// Number of iterations of the gradient search (field_gradient_at, at least 1).
+    /// Each iteration searches gradient_sample_num directions within one step of the previous
+    /// one, so the angle is resolved to about 2 * PI / (gradient_sample_num - 1)^gradient_iteration
+    /// at the cost of gradient_sample_num samples per iteration
+    pub gradient_iteration: u32,
+
+    pub atmosphere_pressure_scale: f64,
+    /// [0.0, 1.0] Proportion of the noise blended into the latitudinal base pressure
+    pub atmosphere_pressure_noise_prop: f64,
+    /// Range of the noise blended into the sea level pressure
+    /// (see PressureNoiseBlend for the resulting range)
+    pub atmosphere_pressure_noise_blend: PressureNoiseBlend,
+    /// Radius of the blur of the sea level pressure from which the atmosphere current is
+    /// derived (a 3x3 binomial kernel with the samples spaced by the radius, multiplying the
+    /// pressure samples of the gradient by 9; 0.0: disabled). The reported pressure is not
+    /// smoothed.
+    pub pressure_smoothing_radius: f64,
+    /// Scale height of the atmosphere pressure (m):
+    /// atmosphere_pressure_normalized =
+    /// floor + (sea_level_pressure_normalized - floor) * exp(-elevation / pressure_altitude_scale),
+    /// where floor is the lower bound of sea_level_pressure_normalized
+    /// (-atmosphere_pressure_noise_prop, or 0.0 with PressureNoiseBlend::Unsigned),
+    /// so the pressure never increases with the elevation
+    pub pressure_altitude_scale: f64,
+    /// Decrease of the temperature per altitude (degree/m), used by vertical_profile
+    pub temperature_lapse_rate: f64,
+    /// Wind speed (m/s) per unit of atmosphere_current_magnitude
+    pub wind_speed_scale: f64,
+    pub circulation_model: CirculationModel,
+    /// Lower bound of |sin(virtual_latitude)| in CirculationModel::Geostrophic,
+    /// capping the wind near the equator at 1 / geostrophic_min_coriolis times the gradient
+    pub geostrophic_min_coriolis: f64,
+
+    /// Compute the atmosphere pressure and current
+    /// (if disabled, the atmosphere fields of [EnvironmentFactors](crate::EnvironmentFactors)
+    /// are None, and no noise is sampled for them)
+    pub compute_atmosphere: bool,
+    /// Compute the ocean current
+    /// (if disabled, the ocean current fields of
+    /// [EnvironmentFactors](crate::EnvironmentFactors) are None, no noise is sampled for them,
+    /// and the temperature is not shifted by the current)
+    pub compute_ocean: bool,
+}
+
+impl ReferenceEnvironmentParameters {
+    /// Checks the fields whose values the provider cannot give a meaning to,
+    /// reporting the first invalid one
+    pub fn validate(&self) -> Result<(), ParameterError> {
+        validate_land_power(self.primitive_land_power)?;
+        validate_octave_amplitudes(self.primitive_land_octave_amplitudes.as_deref())
+    }
+}
+
+impl Default for ReferenceEnvironmentParameters {
+    fn default() -> Self {
+        Self {
+            primitive_shelf_scale: 1.0,
+            primitive_shelf_power: 0.5,
+            primitive_shelf_depth: 0.3,
+            primitive_abyssal_depth: None,
+
+            primitive_persistence_range: ValueRange { min: 0.2, max: 0.8 },
+            primitive_persistence_scale: 0.3,
+
+            primitive_land_scale: 1.0,
+            primitive_land_power: 2.0,
+            primitive_land_octave_amplitudes: None,
+            ocean_floor_scale: None,
+            land_shaping_fn: None,
+            shelf_blend_smoothness: 0.0,
+            boundary_land_falloff: 0.0,
+            land_elevation_scale: 1.0,
+            ocean_depth_scale: 1.0,
+            shelf_class_depth: 0.1,
+
+            primitive_elevation_range: ValueRange {
+                min: -5000.0,
+                max: 5000.0,
+            },
+            elevation_bias: 0.0,
+            coordinate_meters_per_unit: 5.0e6,
+
+            fjord_strength: 0.0,
+            fjord_scale: 0.05,
+
+            ocean_current_scale: 0.8,
+            ocean_current_detail_scale: 0.2,
+            ocean_current_detail_weight: 0.0,
+            ocean_current_elevation_effect_distance: 0.3,
+            ocean_heat_transport_steps: 0,
+            global_ocean_drift: None,
+            ocean_current_output: CurrentOutput::default(),
+            current_magnitude_clamp: None,
+            current_ignorable_magnitude: 0.0,
+
+            virtual_latitude_fn: LatitudeModel::default().into_fn(),
+            valid_fn: ValidModel::default().into_fn(),
+            valid_elevation_fn: None,
+            temperature_surface_fn: TemperatureModel::default().into_fn(),
+            temperature_input: TemperatureInput::default(),
+            temperature_profile: None,
+            temperature_clamp: None,
+            temperature_floor: None,
+            temperature_floor_softness: 5.0,
+            temperature_noise_prop: 0.0,
+            temperature_noise_band: 10.0,
+            temperature_noise_scale: 0.2,
+
+            diurnal_range_base: 2.0,
+            diurnal_range_modulation: 12.0,
+
+            noise_origin: (0.0, 0.0),
+            octave_rotation: 0.0,
+            domain_rotation: 0.0,
+            polar_convergence: false,
+            polar_convergence_min_scale: 0.1,
+
+            edge_mode: EdgeMode::default(),
+            x_wrap_period: 0.0,
+            x_wrap_min: 0.0,
+
+            gradient_sample_num: 16,
+            gradient_iteration: 2,
+
+            atmosphere_pressure_scale: 1.0,
+            atmosphere_pressure_noise_prop: 0.2,
+            atmosphere_pressure_noise_blend: PressureNoiseBlend::default(),
+            pressure_smoothing_radius: 0.0,
+            pressure_altitude_scale: 8000.0,
+            temperature_lapse_rate: 0.0065,
+            wind_speed_scale: 20.0,
+            circulation_model: CirculationModel::default(),
+            geostrophic_min_coriolis: 0.1,
+
+            compute_atmosphere: true,
+            compute_ocean: true,
+        }
+    }
+}
+
+/// Model deriving the atmosphere current from the sea level pressure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CirculationModel {
+    /// Descent of the pressure gradient, deflected by a heuristic latitudinal offset
+    #[default]
+    Heuristic,
+    /// Geostrophic wind: v = (k x grad(p)) / f, where f = sin(virtual_latitude)
+    /// (the Coriolis parameter 2 * Omega * sin(latitude) normalized by 2 * Omega).
+    /// The wind runs along the isobars, and |v| = |grad(p)| / |f| grows without bound
+    /// toward the equator, so |f| is clamped to geostrophic_min_coriolis.
+    Geostrophic,
+}
+
+/// Output of the ocean current in [EnvironmentFactors](crate::EnvironmentFactors)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CurrentOutput {
+    /// The magnitude as computed
+    #[default]
+    Full,
+    /// The magnitude is 1.0 everywhere (uniform arrows); the angle is turned by PI where
+    /// the magnitude was negative, keeping the direction of magnitude * (cos, sin) of the angle
+    DirectionOnly,
+}
+
+/// Treatment of points outside the valid area (valid_fn is false) in gradient calculation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EdgeMode {
+    /// Sample the fields outside the valid area as they are
+    #[default]
+    Extend,
+    /// Reuse the value at the center of the gradient (the nearest valid point)
+    Clamp,
+    /// Drop the sample
+    Skip,
+    /// Wrap x into [x_wrap_min, x_wrap_min + x_wrap_period), so the gradient is continuous
+    /// across the seam of a periodic world (Clamp if the wrapped point is still invalid)
+    Wrap,
+}
+
+/// Built-in virtual_latitude_fn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LatitudeModel {
+    /// sin(y * PI / 4)
+    #[default]
+    Sinusoidal,
+    /// y * PI / 2
+    Linear,
+    /// Set virtual_latitude_fn after the conversion (Sinusoidal until then)
+    Custom,
+}
+
+impl LatitudeModel {
+    pub fn into_fn(self) -> Box<dyn Fn(f64, f64) -> f64 + Send + Sync> {
+        match self {
+            Self::Sinusoidal | Self::Custom => {
+                Box::new(|_, y| (y * std::f64::consts::PI / 4.0).sin())
+            }
+            Self::Linear => Box::new(|_, y| y * std::f64::consts::FRAC_PI_2),
+        }
+    }
+}
+
+/// Blending of the pressure noise into the latitudinal base pressure [0.0, 1.0]:
+/// base * (1.0 - atmosphere_pressure_noise_prop) + noise * atmosphere_pressure_noise_prop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PressureNoiseBlend {
+    /// The noise as sampled [-1.0, 1.0]
+    /// (the pressure is in [-atmosphere_pressure_noise_prop, 1.0])
+    #[default]
+    Signed,
+    /// The noise mapped onto [0.0, 1.0] (noise * 0.5 + 0.5), keeping the pressure in [0.0, 1.0]
+    Unsigned,
+}
+
+/// Input given to temperature_surface_fn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TemperatureInput {
+    /// virtual_latitude (radian) [-PI/2, PI/2]
+    #[default]
+    Latitude,
+    /// Normalized insolation [0.0, 1.0] = cos(virtual_latitude)
+    /// (1.0 at the equator, 0.0 at the poles)
+    Insolation,
+}
+
+/// Built-in temperature_surface_fn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TemperatureModel {
+    /// 30.0 * (1.0 - sin(|latitude|) * 3.0)
+    #[default]
+    Reference,
+    /// Set temperature_surface_fn after the conversion (Reference until then)
+    Custom,
+}
+
+impl TemperatureModel {
+    pub fn into_fn(self) -> Box<dyn Fn(f64) -> f64 + Send + Sync> {
+        match self {
+            Self::Reference | Self::Custom => Box::new(|lat| 30.0 * (1.0 - lat.abs().sin() * 3.0)),
+        }
+    }
+}
+
+/// Temperature of the surface interpolated from the equator to the poles:
+/// pole_temperature + (equator_temperature - pole_temperature) * cos(virtual_latitude)
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TemperatureProfile {
+    /// (degree) at virtual_latitude 0.0
+    pub equator_temperature: f64,
+    /// (degree) at virtual_latitude +-PI/2
+    pub pole_temperature: f64,
+}
+
+impl TemperatureProfile {
+    pub fn temperature(&self, latitude: f64) -> f64 {
+        self.pole_temperature + (self.equator_temperature - self.pole_temperature) * latitude.cos()
+    }
+}
+
+/// Built-in valid_fn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ValidModel {
+    /// |y| < 1.0
+    #[default]
+    Band,
+    /// Valid everywhere
+    Everywhere,
+    /// Set valid_fn after the conversion (Band until then)
+    Custom,
+}
+
+impl ValidModel {
+    pub fn into_fn(self) -> Box<dyn Fn(f64, f64) -> bool + Send + Sync> {
+        match self {
+            Self::Band | Self::Custom => Box::new(|_, y| y.abs() < 1.0),
+            Self::Everywhere => Box::new(|_, _| true),
+        }
+    }
+}
+
+/// (x, y, elevation) -> valid or not
+pub type ValidElevationFn = Box<dyn Fn(f64, f64, &ValueWithNormalized) -> bool + Send + Sync>;
+
+/// Serializable counterpart of [ReferenceEnvironmentParameters]
+/// (the closures are selected from the built-in models,
+/// and land_shaping_fn and valid_elevation_fn are unset)
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct ReferenceEnvironmentParametersConfig {
+    pub primitive_shelf_scale: f64,
+    pub primitive_shelf_power: f64,
+    pub primitive_shelf_depth: f64,
+    pub primitive_abyssal_depth: Option<f64>,
+    pub primitive_persistence_range: ValueRange,
+    pub primitive_persistence_scale: f64,
+    pub primitive_land_scale: f64,
+    pub primitive_land_power: f64,
+    pub primitive_land_octave_amplitudes: Option<Vec<f64>>,
+    pub ocean_floor_scale: Option<f64>,
+    pub shelf_blend_smoothness: f64,
+    pub boundary_land_falloff: f64,
+    pub land_elevation_scale: f64,
+    pub ocean_depth_scale: f64,
+    pub shelf_class_depth: f64,
+    pub primitive_elevation_range: ValueRange,
+    pub elevation_bias: f64,
+    pub coordinate_meters_per_unit: f64,
+    pub fjord_strength: f64,
+    pub fjord_scale: f64,
+    pub ocean_current_scale: f64,
+    pub ocean_current_detail_scale: f64,
+    pub ocean_current_detail_weight: f64,
+    pub ocean_current_elevation_effect_distance: f64,
+    pub ocean_heat_transport_steps: u32,
+    pub global_ocean_drift: Option<(f64, f64)>,
+    pub ocean_current_output: CurrentOutput,
+    pub current_magnitude_clamp: Option<f64>,
+    pub current_ignorable_magnitude: f64,
+    pub diurnal_range_base: f64,
+    pub diurnal_range_modulation: f64,
+    pub noise_origin: (f64, f64),
+    pub octave_rotation: f64,
+    pub domain_rotation: f64,
+    pub polar_convergence: bool,
+    pub polar_convergence_min_scale: f64,
+    pub edge_mode: EdgeMode,
+    pub x_wrap_period: f64,
+    pub x_wrap_min: f64,
+    pub gradient_sample_num: i32,
+    pub gradient_iteration: u32,
+    pub atmosphere_pressure_scale: f64,
+    pub atmosphere_pressure_noise_prop: f64,
+    pub atmosphere_pressure_noise_blend: PressureNoiseBlend,
+    pub pressure_smoothing_radius: f64,
+    pub pressure_altitude_scale: f64,
+    pub temperature_lapse_rate: f64,
+    pub wind_speed_scale: f64,
+    pub circulation_model: CirculationModel,
+    pub geostrophic_min_coriolis: f64,
+    pub compute_atmosphere: bool,
+    pub compute_ocean: bool,
+
+    pub latitude_model: LatitudeModel,
+    pub temperature_model: TemperatureModel,
+    pub temperature_input: TemperatureInput,
+    pub temperature_profile: Option<TemperatureProfile>,
+    pub temperature_clamp: Option<ValueRange>,
+    pub temperature_floor: Option<f64>,
+    pub temperature_floor_softness: f64,
+    pub temperature_noise_prop: f64,
+    pub temperature_noise_band: f64,
+    pub temperature_noise_scale: f64,
+    pub valid_model: ValidModel,
+}
+
+impl ReferenceEnvironmentParametersConfig {
+    /// Checks the fields as [ReferenceEnvironmentParameters::validate] does for the parameters
+    /// converted from this config
+    pub fn validate(&self) -> Result<(), ParameterError> {
+        validate_land_power(self.primitive_land_power)?;
+        validate_octave_amplitudes(self.primitive_land_octave_amplitudes.as_deref())
+    }
+}
+
+impl Default for ReferenceEnvironmentParametersConfig {
+    fn default() -> Self {
+        let params = ReferenceEnvironmentParameters::default();
+        Self {
+            primitive_shelf_scale: params.primitive_shelf_scale,
+            primitive_shelf_power: params.primitive_shelf_power,
+            primitive_shelf_depth: params.primitive_shelf_depth,
+            primitive_abyssal_depth: params.primitive_abyssal_depth,
+            primitive_persistence_range: params.primitive_persistence_range,
+            primitive_persistence_scale: params.primitive_persistence_scale,
+            primitive_land_scale: params.primitive_land_scale,
+            primitive_land_power: params.primitive_land_power,
+            primitive_land_octave_amplitudes: params.primitive_land_octave_amplitudes,
+            ocean_floor_scale: params.ocean_floor_scale,
+            shelf_blend_smoothness: params.shelf_blend_smoothness,
+            boundary_land_falloff: params.boundary_land_falloff,
+            land_elevation_scale: params.land_elevation_scale,
+            ocean_depth_scale: params.ocean_depth_scale,
+            shelf_class_depth: params.shelf_class_depth,
+            primitive_elevation_range: params.primitive_elevation_range,
+            elevation_bias: params.elevation_bias,
+            coordinate_meters_per_unit: params.coordinate_meters_per_unit,
+            fjord_strength: params.fjord_strength,
+            fjord_scale: params.fjord_scale,
+            ocean_current_scale: params.ocean_current_scale,
+            ocean_current_detail_scale: params.ocean_current_detail_scale,
+            ocean_current_detail_weight: params.ocean_current_detail_weight,
+            ocean_current_elevation_effect_distance: params.ocean_current_elevation_effect_distance,
+            ocean_heat_transport_steps: params.ocean_heat_transport_steps,
+            global_ocean_drift: params.global_ocean_drift,
+            ocean_current_output: params.ocean_current_output,
+            current_magnitude_clamp: params.current_magnitude_clamp,
+            current_ignorable_magnitude: params.current_ignorable_magnitude,
+            diurnal_range_base: params.diurnal_range_base,
+            diurnal_range_modulation: params.diurnal_range_modulation,
+            noise_origin: params.noise_origin,
+            octave_rotation: params.octave_rotation,
+            domain_rotation: params.domain_rotation,
+            polar_convergence: params.polar_convergence,
+            polar_convergence_min_scale: params.polar_convergence_min_scale,
+            edge_mode: params.edge_mode,
+            x_wrap_period: params.x_wrap_period,
+            x_wrap_min: params.x_wrap_min,
+            gradient_sample_num: params.gradient_sample_num,
+            gradient_iteration: params.gradient_iteration,
+            atmosphere_pressure_scale: params.atmosphere_pressure_scale,
+            atmosphere_pressure_noise_prop: params.atmosphere_pressure_noise_prop,
+            atmosphere_pressure_noise_blend: params.atmosphere_pressure_noise_blend,
+            pressure_smoothing_radius: params.pressure_smoothing_radius,
+            pressure_altitude_scale: params.pressure_altitude_scale,
+            temperature_lapse_rate: params.temperature_lapse_rate,
+            wind_speed_scale: params.wind_speed_scale,
+            circulation_model: params.circulation_model,
+            geostrophic_min_coriolis: params.geostrophic_min_coriolis,
+            compute_atmosphere: params.compute_atmosphere,
+            compute_ocean: params.compute_ocean,
+
+            latitude_model: LatitudeModel::default(),
+            temperature_model: TemperatureModel::default(),
+            temperature_input: params.temperature_input,
+            temperature_profile: params.temperature_profile,
+            temperature_clamp: params.temperature_clamp,
+            temperature_floor: params.temperature_floor,
+            temperature_floor_softness: params.temperature_floor_softness,
+            temperature_noise_prop: params.temperature_noise_prop,
+            temperature_noise_band: params.temperature_noise_band,
+            temperature_noise_scale: params.temperature_noise_scale,
+            valid_model: ValidModel::default(),
+        }
+    }
+}
+
+impl From<ReferenceEnvironmentParametersConfig> for ReferenceEnvironmentParameters {
+    fn from(config: ReferenceEnvironmentParametersConfig) -> Self {
+        Self {
+            primitive_shelf_scale: config.primitive_shelf_scale,
+            primitive_shelf_power: config.primitive_shelf_power,
+            primitive_shelf_depth: config.primitive_shelf_depth,
+            primitive_abyssal_depth: config.primitive_abyssal_depth,
+            primitive_persistence_range: config.primitive_persistence_range,
+            primitive_persistence_scale: config.primitive_persistence_scale,
+            primitive_land_scale: config.primitive_land_scale,
+            primitive_land_power: config.primitive_land_power,
+            primitive_land_octave_amplitudes: config.primitive_land_octave_amplitudes,
+            ocean_floor_scale: config.ocean_floor_scale,
+            shelf_blend_smoothness: config.shelf_blend_smoothness,
+            boundary_land_falloff: config.boundary_land_falloff,
+            land_elevation_scale: config.land_elevation_scale,
+            ocean_depth_scale: config.ocean_depth_scale,
+            shelf_class_depth: config.shelf_class_depth,
+            primitive_elevation_range: config.primitive_elevation_range,
+            elevation_bias: config.elevation_bias,
+            coordinate_meters_per_unit: config.coordinate_meters_per_unit,
+            fjord_strength: config.fjord_strength,
+            fjord_scale: config.fjord_scale,
+            ocean_current_scale: config.ocean_current_scale,
+            ocean_current_detail_scale: config.ocean_current_detail_scale,
+            ocean_current_detail_weight: config.ocean_current_detail_weight,
+            ocean_current_elevation_effect_distance: config.ocean_current_elevation_effect_distance,
+            ocean_heat_transport_steps: config.ocean_heat_transport_steps,
+            global_ocean_drift: config.global_ocean_drift,
+            ocean_current_output: config.ocean_current_output,
+            current_magnitude_clamp: config.current_magnitude_clamp,
+            current_ignorable_magnitude: config.current_ignorable_magnitude,
+            diurnal_range_base: config.diurnal_range_base,
+            diurnal_range_modulation: config.diurnal_range_modulation,
+            noise_origin: config.noise_origin,
+            octave_rotation: config.octave_rotation,
+            domain_rotation: config.domain_rotation,
+            polar_convergence: config.polar_convergence,
+            polar_convergence_min_scale: config.polar_convergence_min_scale,
+            edge_mode: config.edge_mode,
+            x_wrap_period: config.x_wrap_period,
+            x_wrap_min: config.x_wrap_min,
+            gradient_sample_num: config.gradient_sample_num,
+            gradient_iteration: config.gradient_iteration,
+            atmosphere_pressure_scale: config.atmosphere_pressure_scale,
+            atmosphere_pressure_noise_prop: config.atmosphere_pressure_noise_prop,
+            atmosphere_pressure_noise_blend: config.atmosphere_pressure_noise_blend,
+            pressure_smoothing_radius: config.pressure_smoothing_radius,
+            pressure_altitude_scale: config.pressure_altitude_scale,
+            temperature_lapse_rate: config.temperature_lapse_rate,
+            wind_speed_scale: config.wind_speed_scale,
+            circulation_model: config.circulation_model,
+            geostrophic_min_coriolis: config.geostrophic_min_coriolis,
+            compute_atmosphere: config.compute_atmosphere,
+            compute_ocean: config.compute_ocean,
+
+            land_shaping_fn: None,
+            virtual_latitude_fn: config.latitude_model.into_fn(),
+            valid_fn: config.valid_model.into_fn(),
+            valid_elevation_fn: None,
+            temperature_surface_fn: config.temperature_model.into_fn(),
+            temperature_input: config.temperature_input,
+            temperature_profile: config.temperature_profile,
+            temperature_clamp: config.temperature_clamp,
+            temperature_floor: config.temperature_floor,
+            temperature_floor_softness: config.temperature_floor_softness,
+            temperature_noise_prop: config.temperature_noise_prop,
+            temperature_noise_band: config.temperature_noise_band,
+            temperature_noise_scale: config.temperature_noise_scale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnvironmentProvider, ReferenceEnvironmentProvider};
+
+    fn with_amplitudes(amplitudes: Vec<f64>) -> ReferenceEnvironmentParameters {
+        ReferenceEnvironmentParameters {
+            primitive_land_octave_amplitudes: Some(amplitudes),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn default_parameters_are_valid() {
+        assert_eq!(ReferenceEnvironmentParameters::default().validate(), Ok(()));
+        assert_eq!(
+            ReferenceEnvironmentParametersConfig::default().validate(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn non_positive_land_power_is_rejected() {
+        for primitive_land_power in [0.0, -1.0, MIN_LAND_POWER / 2.0, f64::NAN] {
+            let params = ReferenceEnvironmentParameters {
+                primitive_land_power,
+                ..Default::default()
+            };
+            let error = params.validate().unwrap_err();
+            assert_eq!(error.field, "primitive_land_power");
+        }
+        let params = ReferenceEnvironmentParameters {
+            primitive_land_power: MIN_LAND_POWER,
+            ..Default::default()
+        };
+        assert_eq!(params.validate(), Ok(()));
+    }
+
+    #[test]
+    fn custom_octave_amplitudes_are_validated() {
+        assert_eq!(with_amplitudes(vec![1.0, 0.0, 0.5]).validate(), Ok(()));
+        for amplitudes in [
+            vec![],
+            vec![0.0, 0.0],
+            vec![1.0, -1.0],
+            vec![1.0, f64::NAN],
+            vec![f64::INFINITY],
+        ] {
+            let error = with_amplitudes(amplitudes.clone()).validate().unwrap_err();
+            assert_eq!(
+                error.field, "primitive_land_octave_amplitudes",
+                "{amplitudes:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn config_is_validated_as_the_parameters() {
+        let configs = [
+            ReferenceEnvironmentParametersConfig {
+                primitive_land_power: 0.0,
+                ..Default::default()
+            },
+            ReferenceEnvironmentParametersConfig {
+                primitive_land_octave_amplitudes: Some(vec![0.0]),
+                ..Default::default()
+            },
+        ];
+        for config in configs {
+            let error = config.validate().unwrap_err();
+            assert_eq!(
+                Err(error),
+                ReferenceEnvironmentParameters::from(config).validate()
+            );
+        }
+    }
+
+    #[test]
+    fn config_converts_to_the_same_parameters() {
+        let config = ReferenceEnvironmentParametersConfig {
+            primitive_land_power: 1.5,
+            primitive_land_octave_amplitudes: Some(vec![1.0, 0.5]),
+            fjord_strength: 0.3,
+            x_wrap_period: 4.0,
+            circulation_model: CirculationModel::Geostrophic,
+            latitude_model: LatitudeModel::Linear,
+            valid_model: ValidModel::Everywhere,
+            ..Default::default()
+        };
+        let params = ReferenceEnvironmentParameters::from(config.clone());
+        assert_eq!(params.primitive_land_power, config.primitive_land_power);
+        assert_eq!(
+            params.primitive_land_octave_amplitudes,
+            config.primitive_land_octave_amplitudes
+        );
+        assert_eq!(params.fjord_strength, config.fjord_strength);
+        assert_eq!(params.x_wrap_period, config.x_wrap_period);
+        assert_eq!(params.circulation_model, config.circulation_model);
+        // the closures of the selected models
+        assert_eq!(
+            (params.virtual_latitude_fn)(0.0, 0.5),
+            std::f64::consts::FRAC_PI_4
+        );
+        assert!((params.valid_fn)(0.0, 5.0));
+        assert!(params.land_shaping_fn.is_none() && params.valid_elevation_fn.is_none());
+
+        // the default config holds the default parameters
+        let default =
+            ReferenceEnvironmentParameters::from(ReferenceEnvironmentParametersConfig::default());
+        let expected = ReferenceEnvironmentParameters::default();
+        assert_eq!(default.primitive_land_power, expected.primitive_land_power);
+        assert_eq!(
+            default.coordinate_meters_per_unit,
+            expected.coordinate_meters_per_unit
+        );
+        assert_eq!(
+            (default.virtual_latitude_fn)(0.3, 0.7),
+            (expected.virtual_latitude_fn)(0.3, 0.7)
+        );
+        assert_eq!((default.valid_fn)(0.0, 1.5), (expected.valid_fn)(0.0, 1.5));
+    }
+
+    #[test]
+    fn config_builds_a_working_provider() {
+        let params =
+            ReferenceEnvironmentParameters::from(ReferenceEnvironmentParametersConfig::default());
+        let provider = ReferenceEnvironmentProvider::new(None, params);
+        let factors = provider.get_factors(0.2, 0.3).unwrap();
+        assert!(factors.temperature_surface.is_finite());
+        assert!(factors
+            .primitive_elevation_factors
+            .elevation
+            .value
+            .is_finite());
+        assert!(provider.get_factors(0.2, 1.5).is_none());
+    }
+}
@@ -0,0 +1,4354 @@
+//! Providers of [crate::EnvironmentFactors]
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, MutexGuard},
+};
+
+use libnoise::{Simplex, Source};
+
+use crate::{noise::*, *};
+
+pub trait EnvironmentProvider {
+    fn get_parameters(&self) -> &ReferenceEnvironmentParameters;
+    fn get_factors(&self, x: f64, y: f64) -> Option<EnvironmentFactors>;
+
+    /// get_factors at a typed position
+    fn get_factors_pos(&self, pos: WorldPos) -> Option<EnvironmentFactors> {
+        self.get_factors(pos.x, pos.y)
+    }
+
+    /// get_factors at each of `points` (in the order of `points`)
+    fn get_factors_points(&self, points: &[(f64, f64)]) -> Vec<Option<EnvironmentFactors>> {
+        points
+            .iter()
+            .map(|&(x, y)| self.get_factors(x, y))
+            .collect()
+    }
+
+    /// get_factors_points evaluated in parallel (the results keep the order of `points`)
+    #[cfg(feature = "rayon")]
+    fn get_factors_points_par(&self, points: &[(f64, f64)]) -> Vec<Option<EnvironmentFactors>>
+    where
+        Self: Sync,
+    {
+        use rayon::prelude::*;
+        points
+            .par_iter()
+            .map(|&(x, y)| self.get_factors(x, y))
+            .collect()
+    }
+
+    /// Primitive elevation only
+    /// (override this when the elevation can be computed cheaper than get_factors)
+    fn elevation_at(&self, x: f64, y: f64) -> Option<ValueWithNormalized> {
+        self.get_factors(x, y)
+            .map(|factors| factors.primitive_elevation_factors.elevation)
+    }
+
+    /// temperature_surface only (degree)
+    fn temperature_at(&self, x: f64, y: f64) -> Option<f64> {
+        self.get_factors(x, y)
+            .map(|factors| factors.temperature_surface)
+    }
+
+    /// (ocean_current_angle, ocean_current_magnitude) only
+    /// (None if the ocean current is not computed)
+    fn current_at(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        self.get_factors(x, y)?
+            .get_vector(FactorLayer::OceanCurrent)
+    }
+}
+
+// the overridable methods are forwarded so that the overrides of the provider are used
+impl<P: EnvironmentProvider + ?Sized> EnvironmentProvider for &P {
+    fn get_parameters(&self) -> &ReferenceEnvironmentParameters {
+        (**self).get_parameters()
+    }
+
+    fn get_factors(&self, x: f64, y: f64) -> Option<EnvironmentFactors> {
+        (**self).get_factors(x, y)
+    }
+
+    fn elevation_at(&self, x: f64, y: f64) -> Option<ValueWithNormalized> {
+        (**self).elevation_at(x, y)
+    }
+
+    fn temperature_at(&self, x: f64, y: f64) -> Option<f64> {
+        (**self).temperature_at(x, y)
+    }
+
+    fn current_at(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        (**self).current_at(x, y)
+    }
+}
+
+impl<P: EnvironmentProvider + ?Sized> EnvironmentProvider for Box<P> {
+    fn get_parameters(&self) -> &ReferenceEnvironmentParameters {
+        (**self).get_parameters()
+    }
+
+    fn get_factors(&self, x: f64, y: f64) -> Option<EnvironmentFactors> {
+        (**self).get_factors(x, y)
+    }
+
+    fn elevation_at(&self, x: f64, y: f64) -> Option<ValueWithNormalized> {
+        (**self).elevation_at(x, y)
+    }
+
+    fn temperature_at(&self, x: f64, y: f64) -> Option<f64> {
+        (**self).temperature_at(x, y)
+    }
+
+    fn current_at(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        (**self).current_at(x, y)
+    }
+}
+
+/// Step (in the domain of each octave) of the central differences in noise_value_and_gradient
+const NOISE_GRADIENT_STEP: f64 = 1e-4;
+
+/// Maximum number of draws per requested point in sample_random
+const MAX_DRAWS_PER_RANDOM_SAMPLE: usize = 64;
+
+/// Step (world coordinates) of the central differences in temperature_gradient_at
+const TEMPERATURE_GRADIENT_STEP: f64 = 1e-3;
+
+/// Step (world coordinates) of the central differences in surface_normal_at
+const SURFACE_NORMAL_STEP: f64 = 1e-3;
+
+/// Number of directions and of distances probed by boundary_proximity
+const BOUNDARY_PROBE_DIRECTIONS: usize = 16;
+
+const BOUNDARY_PROBE_STEPS: usize = 8;
+
+/// Number of steps of the upwind search for the fetch in wave_height_at
+/// (spanning primitive_land_scale)
+const WAVE_FETCH_STEPS: usize = 64;
+
+/// Magnitude of a current under which a streamline stops
+const MIN_STREAMLINE_MAGNITUDE: f64 = 1e-6;
+
+/// Slope (normalized elevation per unit distance) under which the terrain is regarded as flat
+const FLAT_SLOPE_THRESHOLD: f64 = 1e-9;
+
+/// Descent (per unit distance) under which get_gradient and the atmosphere current regard
+/// the field as flat
+const FLAT_GRADIENT_THRESHOLD: f64 = 1e-9;
+
+pub struct ReferenceEnvironmentProvider<N: NoiseSource = Simplex<2>> {
+    noises: Vec<N>,
+
+    params: ReferenceEnvironmentParameters,
+}
+
+impl ReferenceEnvironmentProvider {
+    /// `seeds`: the seed of each noise channel (see with_noises), or None for fixed seeds
+    pub fn new(seeds: Option<[u64; NOISE_END]>, params: ReferenceEnvironmentParameters) -> Self {
+        let noises = if let Some(seeds) = seeds {
+            (0..NOISE_END)
+                .map(|i| Source::simplex(seeds[i]))
+                .collect::<Vec<_>>()
+        } else {
+            // adjacent small seeds can give visibly correlated fields,
+            // so the channel indices are scattered over the whole seed space
+            (0..NOISE_END)
+                .map(|i| Source::simplex(splitmix64(i as u64)))
+                .collect::<Vec<_>>()
+        };
+
+        Self { noises, params }
+    }
+}
+
+impl<N: NoiseSource> ReferenceEnvironmentProvider<N> {
+    /// Provider sampling the given noise sources, one per noise channel:
+    /// 0: continent, 1: persistence, 2: land, 3: ocean current, 4: atmosphere pressure,
+    /// 5: fjords, 6: ocean current detail, 7: temperature
+    /// (missing channels are regarded as 0.0).
+    /// `params` are used as they are; check them with ReferenceEnvironmentParameters::validate
+    pub fn with_noises(noises: Vec<N>, params: ReferenceEnvironmentParameters) -> Self {
+        Self { noises, params }
+    }
+
+    /// Coordinates of (x, y) in the domain of the given octave
+    /// (scaled by its frequency and rotated by octave_rotation per octave)
+    fn octave_point(&self, x: f64, y: f64, octave: u32, frequency: f64) -> (f64, f64) {
+        if self.params.octave_rotation == 0.0 {
+            return (x * frequency, y * frequency);
+        }
+        rotate(
+            x * frequency,
+            y * frequency,
+            self.params.octave_rotation * octave as f64,
+        )
+    }
+
+    fn get_noise(&self, x: f64, y: f64, octaves: usize, persistence: f64, idx: usize) -> f64 {
+        self.get_noise_lod(x, y, octaves, persistence, idx, 0)
+    }
+
+    /// get_noise without the `lod` finest octaves (see lod_octaves)
+    fn get_noise_lod(
+        &self,
+        x: f64,
+        y: f64,
+        octaves: usize,
+        persistence: f64,
+        idx: usize,
+        lod: u32,
+    ) -> f64 {
+        self.get_noise_with_amplitudes(
+            x,
+            y,
+            geometric_amplitudes(octaves, persistence),
+            idx,
+            lod_octaves(octaves, lod),
+        )
+    }
+
+    /// Sum of the octaves weighted by `amplitudes` (from the coarsest, the frequency doubling
+    /// per octave), normalized by the sum of the amplitudes.
+    /// The octaves beyond the first `sampled_octaves` are not sampled (regarded as 0.0)
+    /// but still count in the normalization, so that the coarse octaves keep their weight.
+    fn get_noise_with_amplitudes(
+        &self,
+        x: f64,
+        y: f64,
+        amplitudes: impl IntoIterator<Item = f64>,
+        idx: usize,
+        sampled_octaves: usize,
+    ) -> f64 {
+        if idx >= self.noises.len() {
+            return 0.0;
+        }
+        let mut value = 0.0;
+        let mut frequency = 1.0;
+        let mut max_value = 0.0;
+        for (octave, amplitude) in amplitudes.into_iter().enumerate() {
+            if octave < sampled_octaves {
+                let (ox, oy) = self.octave_point(x, y, octave as u32, frequency);
+                value += self.noises[idx].sample(ox, oy) * amplitude;
+            }
+            max_value += amplitude;
+            frequency *= 2.0;
+        }
+        value / max_value
+    }
+
+    /// Number of octaves of the land noise
+    fn land_octaves(&self) -> usize {
+        self.params
+            .primitive_land_octave_amplitudes
+            .as_ref()
+            .map_or(PRIMITIVE_LAND_OCTAVES, Vec::len)
+    }
+
+    /// Amplitudes of the octaves of the land noise
+    /// (primitive_land_octave_amplitudes, or geometric by `persistence`)
+    fn land_amplitudes(&self, persistence: f64) -> impl Iterator<Item = f64> + '_ {
+        let custom = self.params.primitive_land_octave_amplitudes.as_deref();
+        geometric_amplitudes(self.land_octaves(), persistence)
+            .enumerate()
+            .map(move |(octave, amplitude)| custom.map_or(amplitude, |custom| custom[octave]))
+    }
+
+    /// Returns the value of get_noise_with_amplitudes together with its gradient (d/dx, d/dy)
+    /// (the octaves beyond `sampled_octaves` are skipped in the same way).
+    ///
+    /// Every octave is sampled at its center and at +-NOISE_GRADIENT_STEP along each axis,
+    /// and the center samples are shared with the value. The gradient is accurate to
+    /// O(NOISE_GRADIENT_STEP^2) per octave (on the order of 1e-8 for simplex noise),
+    /// at 5 samples per octave instead of the gradient_sample_num * gradient_iteration
+    /// evaluations needed by get_gradient.
+    fn noise_value_and_gradient(
+        &self,
+        x: f64,
+        y: f64,
+        amplitudes: impl IntoIterator<Item = f64>,
+        idx: usize,
+        sampled_octaves: usize,
+    ) -> (f64, (f64, f64)) {
+        if idx >= self.noises.len() {
+            return (0.0, (0.0, 0.0));
+        }
+        let h = NOISE_GRADIENT_STEP;
+        let mut value = 0.0;
+        let mut gradient = (0.0, 0.0);
+        let mut frequency = 1.0;
+        let mut max_value = 0.0;
+        for (octave, amplitude) in amplitudes.into_iter().enumerate() {
+            if octave >= sampled_octaves {
+                max_value += amplitude;
+                continue;
+            }
+            let octave = octave as u32;
+            let (fx, fy) = self.octave_point(x, y, octave, frequency);
+            let noise = &self.noises[idx];
+            value += noise.sample(fx, fy) * amplitude;
+            let dx = (noise.sample(fx + h, fy) - noise.sample(fx - h, fy)) / (2.0 * h);
+            let dy = (noise.sample(fx, fy + h) - noise.sample(fx, fy - h)) / (2.0 * h);
+            // back from the rotated domain of the octave
+            let (dx, dy) = rotate(dx, dy, -self.params.octave_rotation * octave as f64);
+            gradient.0 += dx * frequency * amplitude;
+            gradient.1 += dy * frequency * amplitude;
+            max_value += amplitude;
+            frequency *= 2.0;
+        }
+        (
+            value / max_value,
+            (gradient.0 / max_value, gradient.1 / max_value),
+        )
+    }
+
+    /// land_shaping_fn, or powf(primitive_land_power) if unset
+    fn shape_land(&self, elevation_normalized: f64) -> f64 {
+        match &self.params.land_shaping_fn {
+            Some(land_shaping_fn) => land_shaping_fn(elevation_normalized),
+            None => elevation_normalized.powf(self.params.primitive_land_power),
+        }
+    }
+
+    /// World coordinates -> coordinates of the noise domain (before each layer's scale):
+    /// polar convergence, domain rotation, then noise_origin
+    fn noise_domain(&self, x: f64, y: f64) -> (f64, f64) {
+        let x_scale = if self.params.polar_convergence {
+            (self.params.virtual_latitude_fn)(x, y)
+                .cos()
+                .max(self.params.polar_convergence_min_scale)
+        } else {
+            1.0
+        };
+        let (x, y) = if self.params.domain_rotation == 0.0 {
+            (x * x_scale, y)
+        } else {
+            rotate(x * x_scale, y, self.params.domain_rotation)
+        };
+        (
+            x + self.params.noise_origin.0,
+            y + self.params.noise_origin.1,
+        )
+    }
+
+    /// Gradient (d/dnx, d/dny) in the noise domain at the world coordinates (x, y)
+    /// -> gradient (d/dx, d/dy) in the world coordinates (by the chain rule through noise_domain;
+    /// unchanged where noise_domain is only the shift by noise_origin)
+    fn world_gradient(&self, x: f64, y: f64, (gx, gy): (f64, f64)) -> (f64, f64) {
+        if !self.params.polar_convergence && self.params.domain_rotation == 0.0 {
+            return (gx, gy);
+        }
+        let h = NOISE_GRADIENT_STEP;
+        let (x0, y0) = self.noise_domain(x - h, y);
+        let (x1, y1) = self.noise_domain(x + h, y);
+        let (x2, y2) = self.noise_domain(x, y - h);
+        let (x3, y3) = self.noise_domain(x, y + h);
+        let (dnx_dx, dny_dx) = ((x1 - x0) / (2.0 * h), (y1 - y0) / (2.0 * h));
+        let (dnx_dy, dny_dy) = ((x3 - x2) / (2.0 * h), (y3 - y2) / (2.0 * h));
+        (gx * dnx_dx + gy * dny_dx, gx * dnx_dy + gy * dny_dy)
+    }
+
+    /// Primitive elevation factors at (x, y) if the point passes valid_fn and valid_elevation_fn
+    fn get_valid_primitive_elevation_factors(
+        &self,
+        x: f64,
+        y: f64,
+    ) -> Option<PrimitiveElevationFactors> {
+        if !(self.params.valid_fn)(x, y) {
+            return None;
+        }
+        let primitive_elevation_factors = self.get_primitive_elevation_factors(x, y);
+        self.is_valid_elevation(x, y, &primitive_elevation_factors.elevation)
+            .then_some(primitive_elevation_factors)
+    }
+
+    /// Whether the elevation at (x, y) passes valid_elevation_fn (true if unset)
+    fn is_valid_elevation(&self, x: f64, y: f64, elevation: &ValueWithNormalized) -> bool {
+        match &self.params.valid_elevation_fn {
+            Some(valid_elevation_fn) => valid_elevation_fn(x, y, elevation),
+            None => true,
+        }
+    }
+
+    fn get_primitive_elevation_factors(&self, x: f64, y: f64) -> PrimitiveElevationFactors {
+        self.get_primitive_elevation_factors_with_continent_noise(x, y)
+            .0
+    }
+
+    /// (land base, slope) of the land noise at the coordinates of the noise domain,
+    /// sampled at `scale` (the slope is only computed with the fjords, 0.0 otherwise)
+    fn get_land_noise(&self, x: f64, y: f64, scale: f64, persistence: f64, lod: u32) -> (f64, f64) {
+        let x = x / scale;
+        let y = y / scale;
+        let sampled_octaves = lod_octaves(self.land_octaves(), lod);
+        if self.params.fjord_strength > 0.0 {
+            let (value, (gx, gy)) = self.noise_value_and_gradient(
+                x,
+                y,
+                self.land_amplitudes(persistence),
+                NOISE_PRIMITIVE_LAND,
+                sampled_octaves,
+            );
+            (value.abs(), gx.hypot(gy) / scale)
+        } else {
+            let value = self.get_noise_with_amplitudes(
+                x,
+                y,
+                self.land_amplitudes(persistence),
+                NOISE_PRIMITIVE_LAND,
+                sampled_octaves,
+            );
+            (value.abs(), 0.0)
+        }
+    }
+
+    /// Continent noise at the coordinates of the noise domain (already warped by the fjords)
+    fn get_continent_noise(&self, x: f64, y: f64, lod: u32) -> f64 {
+        let x = x / self.params.primitive_shelf_scale;
+        let y = y / self.params.primitive_shelf_scale;
+        self.get_noise_lod(x, y, 3, 0.5, NOISE_PRIMITIVE_CONTINENT, lod)
+    }
+
+    /// get_primitive_elevation_factors together with the raw continent noise
+    /// the shelf is computed from
+    fn get_primitive_elevation_factors_with_continent_noise(
+        &self,
+        x: f64,
+        y: f64,
+    ) -> (PrimitiveElevationFactors, f64) {
+        self.get_primitive_elevation_factors_with_continent(x, y, None, 0)
+    }
+
+    /// get_primitive_elevation_factors_with_continent_noise with the continent noise given by
+    /// `continent` ((x, y) in the world coordinates, displaced by the fjords -> [-1.0, 1.0])
+    /// instead of NOISE_PRIMITIVE_CONTINENT if set, and without the `lod` finest octaves
+    /// of each noise (see lod_octaves)
+    fn get_primitive_elevation_factors_with_continent(
+        &self,
+        x: f64,
+        y: f64,
+        continent: Option<&dyn Fn(f64, f64) -> f64>,
+        lod: u32,
+    ) -> (PrimitiveElevationFactors, f64) {
+        let (world_x, world_y) = (x, y);
+        let (x, y) = self.noise_domain(x, y);
+        let continent_noise = |(dx, dy): (f64, f64)| match continent {
+            Some(continent) => continent(world_x + dx, world_y + dy),
+            None => self.get_continent_noise(x + dx, y + dy, lod),
+        };
+
+        let primitive_persistence = {
+            let x = x / self.params.primitive_persistence_scale;
+            let y = y / self.params.primitive_persistence_scale;
+
+            ValueWithNormalized::from_normalized(
+                self.get_noise_lod(x, y, 3, 0.5, NOISE_PRIMITIVE_PERSISTENCE, lod) * 0.5 + 0.5,
+                self.params.primitive_persistence_range,
+            )
+        };
+
+        let (primitive_land_base, primitive_land_slope) = {
+            let persistence = primitive_persistence
+                .value
+                .clamp(0.0, MAX_STABLE_PERSISTENCE);
+            let land_scale = self.params.primitive_land_scale;
+            let (land_base, land_slope) = self.get_land_noise(x, y, land_scale, persistence, lod);
+            match self.params.ocean_floor_scale {
+                Some(ocean_floor_scale) if ocean_floor_scale != land_scale => {
+                    let (floor_base, floor_slope) =
+                        self.get_land_noise(x, y, ocean_floor_scale, persistence, lod);
+                    // continentalness of the unwarped continent (0.0: deep ocean, 1.0: inland)
+                    let n = continent_noise((0.0, 0.0));
+                    let shaped = n.abs().powf(self.params.primitive_shelf_power) * n.signum();
+                    let t = (shaped * 0.5 + 0.5).clamp(0.0, 1.0);
+                    (
+                        floor_base * (1.0 - t) + land_base * t,
+                        floor_slope * (1.0 - t) + land_slope * t,
+                    )
+                }
+                _ => (land_base, land_slope),
+            }
+        };
+
+        let (primitive_shelf, continent_noise) = {
+            // steep land crenellates the coastline by warping the continent
+            let displacement = if self.params.fjord_strength > 0.0 {
+                let warp =
+                    self.params.fjord_strength * primitive_land_slope * self.params.fjord_scale;
+                let wx = x / self.params.fjord_scale;
+                let wy = y / self.params.fjord_scale;
+                (
+                    warp * self.get_noise_lod(wx, wy, 4, 0.5, NOISE_FJORD, lod),
+                    warp * self.get_noise_lod(wx + 100.0, wy + 100.0, 4, 0.5, NOISE_FJORD, lod),
+                )
+            } else {
+                (0.0, 0.0)
+            };
+            let n = continent_noise(displacement);
+            let sign = n.signum();
+            let shelf = (n.abs().powf(self.params.primitive_shelf_power) * sign - 1.0)
+                * self.params.primitive_shelf_depth;
+            (shelf, n)
+        };
+
+        let mut primitive_elevation_normalized = primitive_land_base + primitive_shelf;
+        let smoothness = self.params.shelf_blend_smoothness;
+        if smoothness > 0.0 {
+            // blend the raw and the shaped elevation around the sea level
+            let t = smoothstep(-smoothness, smoothness, primitive_elevation_normalized);
+            let shaped = self.shape_land(primitive_elevation_normalized.max(0.0));
+            primitive_elevation_normalized =
+                primitive_elevation_normalized * (1.0 - t) + shaped * t;
+        } else if primitive_elevation_normalized > 0.0 {
+            primitive_elevation_normalized = self.shape_land(primitive_elevation_normalized);
+        }
+        let falloff = self.params.boundary_land_falloff;
+        if falloff > 0.0 {
+            let floor = -self.params.primitive_shelf_depth;
+            let t = smoothstep(
+                0.0,
+                falloff,
+                self.boundary_proximity(world_x, world_y, falloff),
+            );
+            primitive_elevation_normalized = primitive_elevation_normalized
+                .min(primitive_elevation_normalized * t + floor * (1.0 - t));
+        }
+        if let Some(abyssal_depth) = self.params.primitive_abyssal_depth {
+            primitive_elevation_normalized = primitive_elevation_normalized.max(-abyssal_depth);
+        }
+        primitive_elevation_normalized *= if primitive_elevation_normalized > 0.0 {
+            self.params.land_elevation_scale
+        } else {
+            self.params.ocean_depth_scale
+        };
+        let primitive_elevation_normalized = primitive_elevation_normalized
+            .clamp(ELEVATION_NORMALIZED_LIMITS.0, ELEVATION_NORMALIZED_LIMITS.1);
+
+        let range = self.params.primitive_elevation_range;
+        let mut primitive_elevation =
+            ValueWithNormalized::from_signed_normalized(primitive_elevation_normalized, range);
+        if self.params.elevation_bias != 0.0 {
+            primitive_elevation = ValueWithNormalized::from_signed_value(
+                (primitive_elevation.value + self.params.elevation_bias)
+                    .max(range.min)
+                    .min(range.max),
+                range,
+            );
+        }
+
+        (
+            PrimitiveElevationFactors {
+                shelf: primitive_shelf,
+                persistence: primitive_persistence,
+                land_base: primitive_land_base,
+                elevation: primitive_elevation,
+            },
+            continent_noise,
+        )
+    }
+
+    /// Samples `noise_fn` at (x, y) for gradient calculation, treating points outside
+    /// the valid area according to edge_mode (None: the sample is skipped)
+    fn sample_for_gradient(
+        &self,
+        x: f64,
+        y: f64,
+        center_value: f64,
+        noise_fn: &impl Fn(f64, f64) -> f64,
+    ) -> Option<f64> {
+        if (self.params.valid_fn)(x, y) {
+            return Some(noise_fn(x, y));
+        }
+        match self.params.edge_mode {
+            EdgeMode::Extend => Some(noise_fn(x, y)),
+            EdgeMode::Clamp => Some(center_value),
+            EdgeMode::Skip => None,
+            EdgeMode::Wrap => {
+                let period = self.params.x_wrap_period;
+                let min = self.params.x_wrap_min;
+                let x = min + (x - min).rem_euclid(period);
+                if period > 0.0 && (self.params.valid_fn)(x, y) {
+                    Some(noise_fn(x, y))
+                } else {
+                    Some(center_value)
+                }
+            }
+        }
+    }
+
+    /// Returns (angle of the steepest descent or ascent, change of the value per unit
+    /// distance (negative for Descent, positive for Ascent)),
+    /// or (0.0, 0.0) where the field is flat (the zero change carries no direction)
+    /// (`value`: noise_fn(x, y), known to the caller).
+    /// Used by field_gradient_at, for any field and in either direction (the currents are
+    /// derived from the analytic gradients of noise_value_and_gradient instead).
+    fn get_gradient(
+        &self,
+        x: f64,
+        y: f64,
+        value: f64,
+        d: f64,
+        noise_fn: impl Fn(f64, f64) -> f64,
+        direction: GradientDirection,
+    ) -> (f64, f64) {
+        // the ascent is searched as the descent of the negated field
+        let sign = match direction {
+            GradientDirection::Descent => 1.0,
+            GradientDirection::Ascent => -1.0,
+        };
+        let mut final_angle = 0.0;
+        let mut final_value = value;
+        let mut range = (0., std::f64::consts::PI * 2.);
+        for _ in 0..self.params.gradient_iteration.max(1) {
+            let mut min_value = sign * f64::MAX;
+            let mut min_angle = None;
+            let stp = (range.1 - range.0) / (self.params.gradient_sample_num - 1) as f64;
+            for i in 0..self.params.gradient_sample_num {
+                let angle = range.0 + stp * (i as f64);
+                let dx = angle.cos() * d;
+                let dy = angle.sin() * d;
+                let Some(value) = self.sample_for_gradient(x + dx, y + dy, value, &noise_fn) else {
+                    continue;
+                };
+                if sign * value < sign * min_value {
+                    min_value = value;
+                    min_angle = Some(angle);
+                }
+            }
+            let Some(min_angle) = min_angle else {
+                // every sample was skipped
+                break;
+            };
+            range = (min_angle - stp * 0.5, min_angle + stp * 0.5);
+            final_angle = min_angle;
+            final_value = min_value;
+        }
+
+        let diff = (final_value - value) / d;
+        if diff.abs() < FLAT_GRADIENT_THRESHOLD {
+            // no direction is preferred, so any found angle would only be an artifact of the
+            // search (biased toward the first sample); the zero change carries no direction
+            return (0.0, 0.0);
+        }
+
+        (final_angle, diff)
+    }
+
+    /// get_factors at the level of detail `lod`, for zoomed out views.
+    ///
+    /// Every level drops the finest octave of each noise of the elevation (continent,
+    /// persistence, land and fjords), down to their coarsest octave; the dropped octaves keep
+    /// their share of the normalization, so the large-scale shape is unchanged. The octave k
+    /// of the land has the wavelength of about primitive_land_scale / 2^k, so at lod l the
+    /// land has no detail finer than primitive_land_scale / 2^(PRIMITIVE_LAND_OCTAVES - 1 - l);
+    /// choosing l so that this is about the size of a pixel loses no visible detail.
+    /// lod 0 is get_factors. The climate factors are computed as by get_factors.
+    pub fn get_factors_lod(&self, x: f64, y: f64, lod: u32) -> Option<EnvironmentFactors> {
+        if !(self.params.valid_fn)(x, y) {
+            return None;
+        }
+        let (primitive_elevation_factors, _) =
+            self.get_primitive_elevation_factors_with_continent(x, y, None, lod);
+        if !self.is_valid_elevation(x, y, &primitive_elevation_factors.elevation) {
+            return None;
+        }
+        Some(
+            self.get_factors_with_elevation(x, y, primitive_elevation_factors, &|x, y| {
+                self.get_primitive_elevation_factors_with_continent(x, y, None, lod)
+                    .0
+            }),
+        )
+    }
+
+    /// Isolines of `field_selector` for each threshold, traced by marching squares
+    /// on the grid sampling `region` every `resolution`.
+    /// Cells touching an invalid point are skipped.
+    pub fn contours(
+        &self,
+        field_selector: impl Fn(&EnvironmentFactors) -> f64,
+        thresholds: &[f64],
+        region: Region,
+        resolution: f64,
+    ) -> Vec<Contour> {
+        let (cols, rows) = region.grid_size(resolution);
+        let values = region
+            .grid_points(resolution)
+            .map(|(_, (x, y))| {
+                self.get_factors(x, y)
+                    .map(|factors| field_selector(&factors))
+            })
+            .collect::<Vec<_>>();
+
+        thresholds
+            .iter()
+            .map(|&threshold| Contour {
+                threshold,
+                lines: world_isolines(&values, cols, rows, threshold, region, resolution),
+            })
+            .collect()
+    }
+
+    /// Coastline (the isoline of the sea level) on the grid sampling `region` every
+    /// `resolution`, as polylines in the world coordinates
+    /// (closed ones end with their first point; the others end at the edge of the region
+    /// or of the valid area)
+    pub fn coastline(&self, region: Region, resolution: f64) -> Vec<Vec<(f64, f64)>> {
+        self.elevation_isolines(region, resolution, 0.0)
+    }
+
+    /// Total length (in the world coordinates) of the isoline of `sea_level` (a normalized
+    /// elevation) on the grid sampling `region` every `resolution`.
+    /// The length measured at several resolutions probes the ruggedness of the coast
+    /// (it usually grows as the resolution gets finer, faster for a more rugged coastline).
+    pub fn coastline_length(&self, region: Region, resolution: f64, sea_level: f64) -> f64 {
+        self.elevation_isolines(region, resolution, sea_level)
+            .iter()
+            .flat_map(|line| line.windows(2))
+            .map(|segment| {
+                let (x0, y0) = segment[0];
+                let (x1, y1) = segment[1];
+                (x1 - x0).hypot(y1 - y0)
+            })
+            .sum()
+    }
+
+    /// Isolines of the normalized elevation at `threshold` on the grid sampling `region`
+    /// every `resolution`
+    fn elevation_isolines(
+        &self,
+        region: Region,
+        resolution: f64,
+        threshold: f64,
+    ) -> Vec<Vec<(f64, f64)>> {
+        let (cols, rows) = region.grid_size(resolution);
+        let values = region
+            .grid_points(resolution)
+            .map(|(_, (x, y))| {
+                self.elevation_at(x, y)
+                    .map(|elevation| elevation.normalized)
+            })
+            .collect::<Vec<_>>();
+        world_isolines(&values, cols, rows, threshold, region, resolution)
+    }
+
+    /// Local maxima of the elevation on the grid sampling `region` every `resolution`,
+    /// as ((x, y), elevation (m)).
+    ///
+    /// The prominence (m) of a peak is the height of the peak above the highest saddle
+    /// connecting it to a higher peak (or above the lowest sample, for the highest peak).
+    /// Only peaks whose prominence exceeds `min_prominence` are returned, highest first.
+    pub fn find_peaks(
+        &self,
+        region: Region,
+        resolution: f64,
+        min_prominence: f64,
+    ) -> Vec<((f64, f64), f64)> {
+        let (cols, rows) = region.grid_size(resolution);
+        let heights = region
+            .grid_points(resolution)
+            .map(|(_, (x, y))| self.elevation_at(x, y).map(|elevation| elevation.value))
+            .collect::<Vec<_>>();
+
+        let mut order = (0..heights.len())
+            .filter(|&i| heights[i].is_some())
+            .collect::<Vec<_>>();
+        order.sort_by(|&a, &b| heights[b].unwrap().total_cmp(&heights[a].unwrap()));
+        let Some(lowest) = order.last().and_then(|&i| heights[i]) else {
+            return vec![];
+        };
+
+        // union-find over the cells swept from the highest,
+        // where each component is represented by its highest cell (its peak)
+        let mut parent = (0..heights.len()).collect::<Vec<_>>();
+        let find = |parent: &mut Vec<usize>, mut i: usize| {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        };
+        let mut swept = vec![false; heights.len()];
+        let mut prominences: Vec<(usize, f64)> = Vec::new();
+        for &i in &order {
+            let height = heights[i].unwrap();
+            let (ix, iy) = ((i % cols) as i64, (i / cols) as i64);
+            let mut roots = Vec::new();
+            for (dx, dy) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))) {
+                let (nx, ny) = (ix + dx, iy + dy);
+                if nx < 0 || ny < 0 || nx >= cols as i64 || ny >= rows as i64 {
+                    continue;
+                }
+                let j = ny as usize * cols + nx as usize;
+                if swept[j] {
+                    let root = find(&mut parent, j);
+                    if !roots.contains(&root) {
+                        roots.push(root);
+                    }
+                }
+            }
+            swept[i] = true;
+
+            // roots are peaks of their components; the highest one survives this saddle
+            let Some(&highest) = roots
+                .iter()
+                .max_by(|&&a, &&b| heights[a].unwrap().total_cmp(&heights[b].unwrap()))
+            else {
+                // no higher neighbor: a new peak
+                continue;
+            };
+            for &root in &roots {
+                if root != highest {
+                    prominences.push((root, heights[root].unwrap() - height));
+                    parent[root] = highest;
+                }
+            }
+            parent[i] = highest;
+        }
+        for &i in &order {
+            if parent[i] == i {
+                prominences.push((i, heights[i].unwrap() - lowest));
+            }
+        }
+
+        let mut peaks = prominences
+            .into_iter()
+            .filter(|&(_, prominence)| prominence > min_prominence)
+            .map(|(i, _)| {
+                (
+                    region.grid_point(resolution, i % cols, i / cols),
+                    heights[i].unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        peaks.sort_by(|a, b| b.1.total_cmp(&a.1));
+        peaks
+    }
+
+    /// Nearest peak to (x, y) within `search_radius`, with its distance
+    /// (None if there is none). The peaks are the local maxima found by find_peaks
+    /// (with any positive prominence) on the grid sampling the bounding square of the
+    /// circle every `resolution`.
+    pub fn nearest_peak(
+        &self,
+        x: f64,
+        y: f64,
+        search_radius: f64,
+        resolution: f64,
+    ) -> Option<((f64, f64), f64)> {
+        let region = Region {
+            min_x: x - search_radius,
+            min_y: y - search_radius,
+            max_x: x + search_radius,
+            max_y: y + search_radius,
+        };
+        self.find_peaks(region, resolution, 0.0)
+            .into_iter()
+            .map(|(peak, _)| (peak, (peak.0 - x).hypot(peak.1 - y)))
+            .filter(|&(_, distance)| distance <= search_radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    /// Folds `f` over the valid samples of the grid sampling `region` every `resolution`
+    /// (e.g. counting land cells, or averaging the temperature of land only)
+    pub fn reduce_region<T>(
+        &self,
+        region: Region,
+        resolution: f64,
+        init: T,
+        f: impl Fn(T, &EnvironmentFactors) -> T,
+    ) -> T {
+        region
+            .grid_points(resolution)
+            .filter_map(|(_, (x, y))| self.get_factors(x, y))
+            .fold(init, |acc, factors| f(acc, &factors))
+    }
+
+    /// Means of `selector` per band of virtual_latitude, over the valid samples of the grid
+    /// sampling `region` every `resolution`.
+    /// [-PI/2, PI/2] is divided into `bands` equal bands, returned from the south as
+    /// (latitude at the center of the band, mean) (the mean is NaN for a band without samples).
+    pub fn zonal_means(
+        &self,
+        region: Region,
+        resolution: f64,
+        bands: usize,
+        selector: impl Fn(&EnvironmentFactors) -> f64,
+    ) -> Vec<(f64, f64)> {
+        if bands == 0 {
+            return Vec::new();
+        }
+        let band_width = std::f64::consts::PI / bands as f64;
+        let sums = self.reduce_region(
+            region,
+            resolution,
+            vec![(0.0, 0usize); bands],
+            |mut sums, factors| {
+                let band = ((factors.virtual_latitude + std::f64::consts::FRAC_PI_2) / band_width)
+                    .floor()
+                    .clamp(0.0, bands.saturating_sub(1) as f64) as usize;
+                sums[band].0 += selector(factors);
+                sums[band].1 += 1;
+                sums
+            },
+        );
+        sums.into_iter()
+            .enumerate()
+            .map(|(band, (sum, count))| {
+                let latitude = -std::f64::consts::FRAC_PI_2 + (band as f64 + 0.5) * band_width;
+                (latitude, sum / count as f64)
+            })
+            .collect()
+    }
+
+    /// Valid samples within `radius` of `center`, on the grid sampling the bounding square
+    /// of the circle every `resolution`
+    pub fn query_radius(
+        &self,
+        center: (f64, f64),
+        radius: f64,
+        resolution: f64,
+    ) -> Vec<((f64, f64), EnvironmentFactors)> {
+        let region = Region {
+            min_x: center.0 - radius,
+            min_y: center.1 - radius,
+            max_x: center.0 + radius,
+            max_y: center.1 + radius,
+        };
+        region
+            .grid_points(resolution)
+            .map(|(_, point)| point)
+            .filter(|&(x, y)| (x - center.0).hypot(y - center.1) <= radius)
+            .filter_map(|(x, y)| Some(((x, y), self.get_factors(x, y)?)))
+            .collect()
+    }
+
+    /// Land and ocean area (m^2) of `region`, counting each valid sample of the grid
+    /// (every `resolution`) as a cell of resolution^2 and as land if its normalized
+    /// elevation is at or above `sea_level` (a normalized elevation, as for coastline_length;
+    /// 0.0: the sea level)
+    pub fn area_breakdown(&self, region: Region, resolution: f64, sea_level: f64) -> AreaBreakdown {
+        let cell_area = (resolution * self.params.coordinate_meters_per_unit).powi(2);
+        region
+            .grid_points(resolution)
+            .filter_map(|(_, (x, y))| self.elevation_at(x, y))
+            .fold(AreaBreakdown::default(), |mut area, elevation| {
+                if elevation.normalized >= sea_level {
+                    area.land_area += cell_area;
+                } else {
+                    area.ocean_area += cell_area;
+                }
+                area
+            })
+    }
+
+    /// `n` uniformly random valid points of `region` with their factors,
+    /// deterministic for the same `rng_seed` (invalid points are rejected and redrawn).
+    /// Fewer points are returned if the region is (almost) entirely invalid.
+    pub fn sample_random(
+        &self,
+        region: Region,
+        n: usize,
+        rng_seed: u64,
+    ) -> Vec<((f64, f64), EnvironmentFactors)> {
+        let mut state = rng_seed;
+        let mut next_unit = || {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            (splitmix64(state) >> 11) as f64 / (1u64 << 53) as f64
+        };
+        let mut samples = Vec::with_capacity(n);
+        for _ in 0..n.saturating_mul(MAX_DRAWS_PER_RANDOM_SAMPLE) {
+            if samples.len() >= n {
+                break;
+            }
+            let x = region.min_x + next_unit() * (region.max_x - region.min_x);
+            let y = region.min_y + next_unit() * (region.max_y - region.min_y);
+            if let Some(factors) = self.get_factors(x, y) {
+                samples.push(((x, y), factors));
+            }
+        }
+        samples
+    }
+
+    /// Factors at the centers of the hex cells lying in `region`
+    /// (see HexCoord for the layout; None for the invalid points)
+    pub fn get_factors_hexgrid(
+        &self,
+        region: Region,
+        hex_size: f64,
+        orientation: HexOrientation,
+    ) -> Vec<(HexCoord, Option<EnvironmentFactors>)> {
+        HexCoord::cells_in(region, hex_size, orientation)
+            .into_iter()
+            .map(|cell| {
+                let (x, y) = cell.center(hex_size, orientation);
+                (cell, self.get_factors(x, y))
+            })
+            .collect()
+    }
+
+    /// Path following the steepest descent of the elevation from (start_x, start_y),
+    /// advancing `step_len` per step.
+    ///
+    /// The path ends when it reaches the sea level, a flat spot or a local minimum
+    /// (no lower point one step ahead), leaves the valid area, or after `max_steps` steps.
+    pub fn trace_downhill(
+        &self,
+        start_x: f64,
+        start_y: f64,
+        max_steps: usize,
+        step_len: f64,
+    ) -> Vec<(f64, f64)> {
+        self.trace_downhill_with_end(start_x, start_y, max_steps, step_len)
+            .0
+    }
+
+    /// Whether the downhill path from (x, y) ends in a sink (a flat spot or a local minimum)
+    /// above the sea level, rather than reaching the ocean.
+    /// The path advances primitive_land_scale / 256 (about the finest land detail) per step,
+    /// and is regarded as not closed if it leaves the valid area or exceeds `max_steps`.
+    pub fn is_inland_basin(&self, x: f64, y: f64, max_steps: usize) -> bool {
+        let step_len = self.params.primitive_land_scale / 256.0;
+        let (_, end) = self.trace_downhill_with_end(x, y, max_steps, step_len);
+        end == DownhillEnd::Sink
+    }
+
+    /// Estimated work of get_factors on the grid sampling `region` every `resolution`
+    /// (every point is assumed to be valid), derived from the octaves, the gradient
+    /// settings and the enabled factors without sampling anything
+    pub fn estimate_cost(&self, region: Region, resolution: f64) -> GenerationCost {
+        let (cols, rows) = region.grid_size(resolution);
+        let points = (cols * rows) as u64;
+
+        // octaves of the persistence, land and continent noises;
+        // with the fjords, the land is sampled with its gradient (5 samples per octave)
+        // and the warp adds two 4 octave noises
+        let land_octaves = self.land_octaves() as u64;
+        let land = if self.params.fjord_strength > 0.0 {
+            land_octaves * 5
+        } else {
+            land_octaves
+        };
+        let mut elevation = if self.params.fjord_strength > 0.0 {
+            3 + land + 2 * 4 + 3
+        } else {
+            3 + land + 3
+        };
+        // a distinct ocean floor scale samples the land once more, and the unwarped continent
+        if self
+            .params
+            .ocean_floor_scale
+            .is_some_and(|scale| scale != self.params.primitive_land_scale)
+        {
+            elevation += land + 3;
+        }
+        let ocean_current = if self.params.ocean_current_detail_weight != 0.0 {
+            2 * 5
+        } else {
+            5
+        };
+        let mut per_point = elevation;
+        if self.params.compute_ocean {
+            per_point += ocean_current;
+            // every further step of the heat transport re-evaluates the elevation and the current
+            let extra_steps = self.params.ocean_heat_transport_steps.max(1) as u64 - 1;
+            per_point += extra_steps * (elevation + ocean_current);
+        }
+        if self.params.temperature_noise_prop != 0.0 {
+            per_point += 3;
+        }
+        let mut gradient_passes = 0;
+        if self.params.compute_atmosphere {
+            // the sea level pressure with its gradient, at the 9 points of the blur if any
+            // (its center reuses the sea level pressure)
+            per_point += if self.params.pressure_smoothing_radius > 0.0 {
+                9 * 5
+            } else {
+                5
+            };
+            gradient_passes = points;
+        }
+
+        GenerationCost {
+            points,
+            noise_samples: per_point * points,
+            gradient_passes,
+        }
+    }
+
+    /// Current vector (magnitude * (cos, sin) of the angle) of `field` at (x, y)
+    fn current_vector(&self, field: CurrentField, x: f64, y: f64) -> Option<(f64, f64)> {
+        let (angle, magnitude) = match field {
+            CurrentField::Ocean => self.current_at(x, y)?,
+            CurrentField::Atmosphere => self
+                .get_factors(x, y)?
+                .get_vector(FactorLayer::AtmosphereCurrent)?,
+        };
+        Some((magnitude * angle.cos(), magnitude * angle.sin()))
+    }
+
+    /// Streamlines of `field` started from the grid of seeds every `seed_spacing` in `region`,
+    /// integrated (midpoint method, steps of seed_spacing / 4) along the current vector.
+    /// A streamline ends where the magnitude almost vanishes, at the edge of the region or of
+    /// the valid area, or when its length reaches `max_len`.
+    pub fn streamlines(
+        &self,
+        field: CurrentField,
+        region: Region,
+        seed_spacing: f64,
+        max_len: f64,
+    ) -> Vec<Vec<(f64, f64)>> {
+        let step_len = seed_spacing * 0.25;
+        let inside = |(x, y): (f64, f64)| {
+            x >= region.min_x && x <= region.max_x && y >= region.min_y && y <= region.max_y
+        };
+        // unit direction of the current, or None where the streamline ends
+        let direction = |(x, y): (f64, f64)| {
+            if !inside((x, y)) {
+                return None;
+            }
+            let (vx, vy) = self.current_vector(field, x, y)?;
+            let magnitude = vx.hypot(vy);
+            (magnitude.is_finite() && magnitude >= MIN_STREAMLINE_MAGNITUDE)
+                .then(|| (vx / magnitude, vy / magnitude))
+        };
+
+        region
+            .grid_points(seed_spacing)
+            .map(|(_, mut point)| {
+                let mut line = vec![point];
+                let mut len = 0.0;
+                while len + step_len <= max_len {
+                    let Some((dx, dy)) = direction(point) else {
+                        break;
+                    };
+                    let mid = (point.0 + dx * step_len * 0.5, point.1 + dy * step_len * 0.5);
+                    let Some((dx, dy)) = direction(mid) else {
+                        break;
+                    };
+                    let next = (point.0 + dx * step_len, point.1 + dy * step_len);
+                    if !inside(next) {
+                        break;
+                    }
+                    point = next;
+                    line.push(point);
+                    len += step_len;
+                }
+                line
+            })
+            .filter(|line| line.len() > 1)
+            .collect()
+    }
+
+    /// Atmosphere column above (x, y) at each of `altitudes` (m above the sea level):
+    /// temperature_surface extrapolated from the surface (or the sea level over the ocean)
+    /// by temperature_lapse_rate, and the sea level pressure reduced by
+    /// pressure_altitude_scale as for atmosphere_pressure_normalized
+    pub fn vertical_profile(&self, x: f64, y: f64, altitudes: &[f64]) -> Option<Vec<ColumnSample>> {
+        let factors = self.get_factors(x, y)?;
+        let surface_altitude = factors.primitive_elevation_factors.elevation.value.max(0.0);
+        Some(
+            altitudes
+                .iter()
+                .map(|&altitude| ColumnSample {
+                    altitude,
+                    temperature: factors.temperature_surface
+                        - self.params.temperature_lapse_rate * (altitude - surface_altitude),
+                    pressure_normalized: factors
+                        .sea_level_pressure_normalized
+                        .map(|pressure| self.reduce_pressure(pressure, altitude)),
+                })
+                .collect(),
+        )
+    }
+
+    /// Gradient of temperature_surface at (x, y) as (angle (radian) of the direction of
+    /// the steepest increase, magnitude (degree per unit distance)), by central differences
+    /// over TEMPERATURE_GRADIENT_STEP. Large magnitudes mark thermal fronts.
+    /// None if any of the samples is invalid.
+    pub fn temperature_gradient_at(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let h = TEMPERATURE_GRADIENT_STEP;
+        let gx = (self.temperature_at(x + h, y)? - self.temperature_at(x - h, y)?) / (2.0 * h);
+        let gy = (self.temperature_at(x, y + h)? - self.temperature_at(x, y - h)?) / (2.0 * h);
+        Some((gy.atan2(gx), gx.hypot(gy)))
+    }
+
+    /// Unit normal (x, y, up) of the surface at (x, y), by central differences of
+    /// elevation.value (m) over SURFACE_NORMAL_STEP, the horizontal distances being
+    /// converted to meters by coordinate_meters_per_unit (so the slope is true to scale).
+    /// None if any of the samples is invalid.
+    pub fn surface_normal_at(&self, x: f64, y: f64) -> Option<(f64, f64, f64)> {
+        let h = SURFACE_NORMAL_STEP;
+        let elevation = |x: f64, y: f64| self.elevation_at(x, y).map(|elevation| elevation.value);
+        let distance = 2.0 * h * self.params.coordinate_meters_per_unit;
+        let gx = (elevation(x + h, y)? - elevation(x - h, y)?) / distance;
+        let gy = (elevation(x, y + h)? - elevation(x, y - h)?) / distance;
+        let length = (gx * gx + gy * gy + 1.0).sqrt();
+        Some((-gx / length, -gy / length, 1.0 / length))
+    }
+
+    /// Writes the normal map of `region` sampled every `resolution` (see Region::grid_size)
+    /// to `path` as an RGBA PNG: surface_normal_at packed as n * 0.5 + 0.5 into red (+x),
+    /// green (+y) and blue (up), so a flat surface is (128, 128, 255). The first row is at
+    /// min_y, so green points down the image (the DirectX convention).
+    /// Invalid points are transparent (0, 0, 0, 0).
+    pub fn export_normal_map_png(
+        &self,
+        region: Region,
+        resolution: f64,
+        path: impl AsRef<std::path::Path>,
+    ) -> std::io::Result<()> {
+        let (width, height) = region.grid_size(resolution);
+        let mut pixels = vec![0; width * height * 4];
+        for ((ix, iy), (x, y)) in region.grid_points(resolution) {
+            let Some((nx, ny, nz)) = self.surface_normal_at(x, y) else {
+                continue;
+            };
+            let pack = |n: f64| ((n * 0.5 + 0.5) * 255.0).round() as u8;
+            let pixel = (iy * width + ix) * 4;
+            pixels[pixel..pixel + 4].copy_from_slice(&[pack(nx), pack(ny), pack(nz), 255]);
+        }
+        std::fs::write(path, crate::png::encode_rgba(width, height, &pixels))
+    }
+
+    /// Significant wave height (m) over the ocean at (x, y) (None on land, outside the valid
+    /// area, or if compute_atmosphere is disabled), by the fetch-limited JONSWAP relation
+    /// Hs = 0.0016 * U * sqrt(F / g) capped at the fully developed sea 0.243 * U^2 / g,
+    /// where U is atmosphere_current_speed (m/s) and F the fetch (m): the open water upwind,
+    /// searched in WAVE_FETCH_STEPS steps up to primitive_land_scale until land or the edge
+    /// of the valid area.
+    pub fn wave_height_at(&self, x: f64, y: f64) -> Option<f64> {
+        const G: f64 = 9.81;
+        let factors = self.get_factors(x, y)?;
+        if factors.primitive_elevation_factors.elevation.normalized > 0.0 {
+            return None;
+        }
+        let (angle, magnitude) = factors.get_vector(FactorLayer::AtmosphereCurrent)?;
+        let speed = factors.atmosphere_current_speed?;
+        // the wind blows along magnitude * (cos, sin) of the angle
+        let (sin, cos) = angle.sin_cos();
+        let sign = magnitude.signum();
+        let (upwind_x, upwind_y) = (-cos * sign, -sin * sign);
+        let step = self.params.primitive_land_scale / WAVE_FETCH_STEPS as f64;
+        let open_steps = (1..=WAVE_FETCH_STEPS)
+            .take_while(|&k| {
+                let d = k as f64 * step;
+                self.elevation_at(x + upwind_x * d, y + upwind_y * d)
+                    .is_some_and(|elevation| elevation.normalized <= 0.0)
+            })
+            .count();
+        let fetch = open_steps as f64 * step * self.params.coordinate_meters_per_unit;
+
+        Some((0.0016 * speed * (fetch / G).sqrt()).min(0.243 * speed * speed / G))
+    }
+
+    /// (angle, change per unit distance) of the steepest descent or ascent of
+    /// `field_selector` at (x, y), searched like the atmosphere current (gradient_sample_num
+    /// directions refined gradient_iteration times) on the circle of radius `d`, so the
+    /// descent and the ascent of a monotonic slope are PI apart without negating angles.
+    /// The change is negative for the descent and positive for the ascent;
+    /// (0.0, 0.0) where the field is flat. None if (x, y) is invalid.
+    pub fn field_gradient_at(
+        &self,
+        x: f64,
+        y: f64,
+        d: f64,
+        field_selector: impl Fn(&EnvironmentFactors) -> f64,
+        direction: GradientDirection,
+    ) -> Option<(f64, f64)> {
+        let value = field_selector(&self.get_factors(x, y)?);
+        // samples without factors are NaN, which never wins the search
+        let field_fn = |x: f64, y: f64| {
+            self.get_factors(x, y)
+                .map_or(f64::NAN, |factors| field_selector(&factors))
+        };
+        Some(self.get_gradient(x, y, value, d, field_fn, direction))
+    }
+
+    /// Distance from (x, y) to the edge of the valid area (valid_fn), up to `max_distance`
+    /// (0.0 outside the valid area, max_distance if no edge is found within it).
+    /// The edge is searched on BOUNDARY_PROBE_STEPS rings of BOUNDARY_PROBE_DIRECTIONS
+    /// points, so the distance is accurate to max_distance / BOUNDARY_PROBE_STEPS.
+    pub fn boundary_proximity(&self, x: f64, y: f64, max_distance: f64) -> f64 {
+        if !(self.params.valid_fn)(x, y) {
+            return 0.0;
+        }
+        for step in 1..=BOUNDARY_PROBE_STEPS {
+            let distance = max_distance * step as f64 / BOUNDARY_PROBE_STEPS as f64;
+            let edge_found = (0..BOUNDARY_PROBE_DIRECTIONS).any(|i| {
+                let angle =
+                    std::f64::consts::PI * 2.0 * i as f64 / BOUNDARY_PROBE_DIRECTIONS as f64;
+                !(self.params.valid_fn)(x + angle.cos() * distance, y + angle.sin() * distance)
+            });
+            if edge_found {
+                return distance;
+            }
+        }
+        max_distance
+    }
+
+    /// Raw continent noise [-1.0, 1.0] at (x, y), the input of the shelf
+    /// (primitive_shelf = (|n|^primitive_shelf_power * sign(n) - 1.0) * primitive_shelf_depth),
+    /// for combining the base continent field with external data
+    pub fn continent_noise_at(&self, x: f64, y: f64) -> f64 {
+        if self.params.fjord_strength > 0.0 {
+            // the fjords warp the continent depending on the slope of the land
+            return self
+                .get_primitive_elevation_factors_with_continent_noise(x, y)
+                .1;
+        }
+        let (x, y) = self.noise_domain(x, y);
+        self.get_continent_noise(x, y, 0)
+    }
+
+    /// Bounds (min, max) of elevation.normalized reachable with the parameters,
+    /// within ELEVATION_NORMALIZED_LIMITS.
+    /// The bounds are derived from the ranges of the shelf ([-2 * primitive_shelf_depth, 0.0])
+    /// and the land base ([0.0, 1.0]) through the shaping, the abyssal floor,
+    /// land_elevation_scale / ocean_depth_scale, the clamp and elevation_bias,
+    /// assuming noise sources in [-1.0, 1.0]; they enclose every sample but are reached only
+    /// where the noise is at its extremes.
+    pub fn elevation_normalized_bounds(&self) -> (f64, f64) {
+        const SHAPING_SAMPLES: usize = 1024;
+        let (shaped_min, shaped_max) = (0..=SHAPING_SAMPLES)
+            .map(|i| self.shape_land(i as f64 / SHAPING_SAMPLES as f64))
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+                (min.min(v), max.max(v))
+            });
+
+        // the smooth blend lies between the raw and the shaped elevation
+        let raw_min = -2.0 * self.params.primitive_shelf_depth;
+        let raw_max = self.params.shelf_blend_smoothness.clamp(0.0, 1.0);
+        let mut min = raw_min.min(shaped_min);
+        let max = raw_max.max(shaped_max);
+        if let Some(abyssal_depth) = self.params.primitive_abyssal_depth {
+            min = min.max(-abyssal_depth);
+        }
+        let scale = |v: f64| {
+            v * if v > 0.0 {
+                self.params.land_elevation_scale
+            } else {
+                self.params.ocean_depth_scale
+            }
+        };
+        let (limit_min, limit_max) = ELEVATION_NORMALIZED_LIMITS;
+        let range = self.params.primitive_elevation_range;
+        let bias = 2.0 * self.params.elevation_bias / (range.max - range.min);
+        let clamp_biased =
+            |v: f64| (v.clamp(limit_min, limit_max) + bias).clamp(limit_min, limit_max);
+        (clamp_biased(scale(min)), clamp_biased(scale(max)))
+    }
+
+    fn trace_downhill_with_end(
+        &self,
+        start_x: f64,
+        start_y: f64,
+        max_steps: usize,
+        step_len: f64,
+    ) -> (Vec<(f64, f64)>, DownhillEnd) {
+        let mut path = vec![(start_x, start_y)];
+        let (mut x, mut y) = (start_x, start_y);
+        let Some(mut elevation) = self.elevation_at(x, y) else {
+            return (path, DownhillEnd::Invalid);
+        };
+        let h = step_len * 0.5;
+        for _ in 0..max_steps {
+            if elevation.normalized <= 0.0 {
+                return (path, DownhillEnd::SeaLevel);
+            }
+            let (Some(ex0), Some(ex1), Some(ey0), Some(ey1)) = (
+                self.elevation_at(x - h, y),
+                self.elevation_at(x + h, y),
+                self.elevation_at(x, y - h),
+                self.elevation_at(x, y + h),
+            ) else {
+                return (path, DownhillEnd::Invalid);
+            };
+            let gx = (ex1.normalized - ex0.normalized) / (2.0 * h);
+            let gy = (ey1.normalized - ey0.normalized) / (2.0 * h);
+            let slope = gx.hypot(gy);
+            if slope < FLAT_SLOPE_THRESHOLD {
+                return (path, DownhillEnd::Sink);
+            }
+            let (nx, ny) = (x - gx / slope * step_len, y - gy / slope * step_len);
+            let Some(next_elevation) = self.elevation_at(nx, ny) else {
+                return (path, DownhillEnd::Invalid);
+            };
+            if next_elevation.normalized >= elevation.normalized {
+                return (path, DownhillEnd::Sink);
+            }
+            (x, y, elevation) = (nx, ny, next_elevation);
+            path.push((x, y));
+        }
+        let end = if elevation.normalized <= 0.0 {
+            DownhillEnd::SeaLevel
+        } else {
+            DownhillEnd::MaxSteps
+        };
+        (path, end)
+    }
+
+    /// Returns (ocean_current_angle, ocean_current_magnitude)
+    /// (`samples`: the samples at (x, y) if already known)
+    fn get_ocean_current(
+        &self,
+        x: f64,
+        y: f64,
+        primitive_elevation_factors: &PrimitiveElevationFactors,
+        samples: Option<&PointSamples>,
+    ) -> (f64, f64) {
+        let (ocean_current_angle, ocean_current_diff) = {
+            let (nx, ny) = self.noise_domain(x, y);
+            let (_, (gx, gy)) = self.noise_value_and_gradient(
+                nx / self.params.ocean_current_scale,
+                ny / self.params.ocean_current_scale,
+                geometric_amplitudes(1, 0.0),
+                NOISE_OCEAN_CURRENT,
+                1,
+            );
+            let (mut gx, mut gy) = (
+                gx / self.params.ocean_current_scale,
+                gy / self.params.ocean_current_scale,
+            );
+            if self.params.ocean_current_detail_weight != 0.0 {
+                // smaller eddies on top of the large gyres
+                let scale = self.params.ocean_current_detail_scale;
+                let (_, (dgx, dgy)) = self.noise_value_and_gradient(
+                    nx / scale,
+                    ny / scale,
+                    geometric_amplitudes(1, 0.0),
+                    NOISE_OCEAN_CURRENT_DETAIL,
+                    1,
+                );
+                gx += dgx / scale * self.params.ocean_current_detail_weight;
+                gy += dgy / scale * self.params.ocean_current_detail_weight;
+            }
+            let (gx, gy) = self.world_gradient(x, y, (gx, gy));
+            // same convention as get_gradient: the angle of descent and the (negative) slope
+            let gradient_angle = (-gy).atan2(-gx);
+            let diff = -gx.hypot(gy);
+            (gradient_angle + std::f64::consts::PI / 4.0, diff)
+        };
+
+        // this maximum value is not accurate, but enough for this purpose
+        let perlin_noise_max_gradient = {
+            let pnmg1d = 30.0 / 8.0;
+            2.0 * (pnmg1d * pnmg1d * 2.0_f64).sqrt()
+        };
+
+        let oceanness = 1.0 - primitive_elevation_factors.elevation.normalized.max(0.0);
+        let ocean_current_magnitude = ocean_current_diff * oceanness / perlin_noise_max_gradient
+            * self.params.ocean_current_scale;
+
+        if let Some((drift_x, drift_y)) = self.params.global_ocean_drift {
+            let latitude = match samples {
+                Some(samples) => samples.virtual_latitude,
+                None => (self.params.virtual_latitude_fn)(x, y),
+            };
+            let band = latitude.cos();
+            let vx =
+                ocean_current_magnitude * ocean_current_angle.cos() + drift_x * oceanness * band;
+            let vy =
+                ocean_current_magnitude * ocean_current_angle.sin() + drift_y * oceanness * band;
+            return (vy.atan2(vx), vx.hypot(vy));
+        }
+
+        (ocean_current_angle, ocean_current_magnitude)
+    }
+
+    /// (angle, magnitude) of the ocean current as output by get_factors
+    /// (current_magnitude_clamp and ocean_current_output applied)
+    fn output_ocean_current(&self, (angle, magnitude): (f64, f64)) -> (f64, f64) {
+        let magnitude = match self.params.current_magnitude_clamp {
+            Some(bound) => magnitude.max(-bound).min(bound),
+            None => magnitude,
+        };
+        match self.params.ocean_current_output {
+            CurrentOutput::Full => (angle, magnitude),
+            CurrentOutput::DirectionOnly if magnitude < 0.0 => (angle + std::f64::consts::PI, 1.0),
+            CurrentOutput::DirectionOnly => (angle, 1.0),
+        }
+    }
+
+    /// (`samples`: the samples at (x, y) if already known, `elevation`: the primitive
+    /// elevation factors the ocean current is traced upstream over)
+    fn get_temperature_surface(
+        &self,
+        x: f64,
+        y: f64,
+        ocean_current: Option<(f64, f64)>,
+        samples: Option<&PointSamples>,
+        elevation: &dyn Fn(f64, f64) -> PrimitiveElevationFactors,
+    ) -> f64 {
+        let (mut ocean_current_angle, mut ocean_current_magnitude) =
+            ocean_current.unwrap_or((0.0, 0.0));
+        let steps = match ocean_current {
+            Some(_) => self.params.ocean_heat_transport_steps.max(1),
+            None => 1,
+        };
+        let step_distance = self.params.ocean_current_elevation_effect_distance / steps as f64;
+        let (mut px, mut py) = (x, y);
+        for step in 0..steps {
+            if step > 0 {
+                let primitive_elevation_factors = elevation(px, py);
+                (ocean_current_angle, ocean_current_magnitude) =
+                    self.get_ocean_current(px, py, &primitive_elevation_factors, None);
+            }
+            // the water comes from upstream, carrying the temperature of its origin
+            px -= ocean_current_angle.cos() * step_distance * ocean_current_magnitude;
+            py -= ocean_current_angle.sin() * step_distance * ocean_current_magnitude;
+        }
+        let temperature_latitude = match samples {
+            Some(samples) if (px, py) == (x, y) => samples.virtual_latitude,
+            _ => (self.params.virtual_latitude_fn)(px, py),
+        };
+
+        let mut temperature = match self.params.temperature_profile {
+            Some(profile) => profile.temperature(temperature_latitude),
+            None => {
+                let input = match self.params.temperature_input {
+                    TemperatureInput::Latitude => temperature_latitude,
+                    TemperatureInput::Insolation => temperature_latitude.cos().max(0.0),
+                };
+                (self.params.temperature_surface_fn)(input)
+            }
+        };
+        if self.params.temperature_noise_prop != 0.0 {
+            // local micro-climate, not carried by the ocean current
+            let (nx, ny) = self.noise_domain(x, y);
+            let scale = self.params.temperature_noise_scale;
+            let noise = self.get_noise(nx / scale, ny / scale, 3, 0.5, NOISE_TEMPERATURE);
+            temperature +=
+                noise * self.params.temperature_noise_prop * self.params.temperature_noise_band;
+        }
+        if let Some(floor) = self.params.temperature_floor {
+            let softness = self.params.temperature_floor_softness;
+            temperature = floor + softness * softplus((temperature - floor) / softness);
+        }
+        match self.params.temperature_clamp {
+            Some(range) => temperature.clamp(range.min, range.max),
+            None => temperature,
+        }
+    }
+
+    /// Sea level pressure reduced to `altitude` (m above the sea level).
+    ///
+    /// The pressure may be negative (see PressureNoiseBlend), so it is reduced as its height
+    /// above its lower bound: the reduced pressure decays from the sea level pressure toward
+    /// the lower bound by exp(-altitude / pressure_altitude_scale), and never increases
+    /// with the altitude.
+    fn reduce_pressure(&self, sea_level_pressure_normalized: f64, altitude: f64) -> f64 {
+        let floor = match self.params.atmosphere_pressure_noise_blend {
+            PressureNoiseBlend::Signed => -self.params.atmosphere_pressure_noise_prop.max(0.0),
+            PressureNoiseBlend::Unsigned => 0.0,
+        };
+        floor
+            + (sea_level_pressure_normalized - floor)
+                * (-altitude / self.params.pressure_altitude_scale).exp()
+    }
+
+    /// Sea level pressure (normalized) at (x, y) together with its gradient (d/dx, d/dy),
+    /// from the shared samples of noise_value_and_gradient (5 noise samples; the clamp of
+    /// the noise has no slope where it applies)
+    fn atmosphere_pressure_value_and_gradient(&self, x: f64, y: f64) -> (f64, (f64, f64)) {
+        let scale = self.params.atmosphere_pressure_scale;
+        let prop = self.params.atmosphere_pressure_noise_prop;
+        let base = -(y * std::f64::consts::PI * 2.0).cos() * 0.5 + 0.5;
+        let base_dy = std::f64::consts::PI * (y * std::f64::consts::PI * 2.0).sin();
+        let (nx, ny) = self.noise_domain(x, y);
+        let (noise, (gx, gy)) = self.noise_value_and_gradient(
+            nx / scale,
+            ny / scale,
+            geometric_amplitudes(1, 0.5),
+            NOISE_ATMOSPHERE_PRESSURE,
+            1,
+        );
+        let (gx, gy) = self.world_gradient(x, y, (gx / scale, gy / scale));
+        let (noise, slope) = match self.params.atmosphere_pressure_noise_blend {
+            PressureNoiseBlend::Signed => (noise, 1.0),
+            PressureNoiseBlend::Unsigned => (noise * 0.5 + 0.5, 0.5),
+        };
+        let (min, max) = match self.params.atmosphere_pressure_noise_blend {
+            PressureNoiseBlend::Signed => (-1.0, 1.0),
+            PressureNoiseBlend::Unsigned => (0.0, 1.0),
+        };
+        let slope = if (min..=max).contains(&noise) {
+            slope
+        } else {
+            0.0
+        };
+        (
+            base * (1.0 - prop) + noise.clamp(min, max) * prop,
+            (
+                gx * slope * prop,
+                base_dy * (1.0 - prop) + gy * slope * prop,
+            ),
+        )
+    }
+
+    /// Factors at (x, y) derived from the given primitive elevation factors
+    /// (the validity is not checked); `elevation` gives the primitive elevation factors
+    /// elsewhere, e.g. upstream of the ocean current
+    fn get_factors_with_elevation(
+        &self,
+        x: f64,
+        y: f64,
+        primitive_elevation_factors: PrimitiveElevationFactors,
+        elevation: &dyn Fn(f64, f64) -> PrimitiveElevationFactors,
+    ) -> EnvironmentFactors {
+        let samples = PointSamples {
+            virtual_latitude: (self.params.virtual_latitude_fn)(x, y),
+        };
+        let latitude = samples.virtual_latitude;
+
+        let ocean_current = self
+            .params
+            .compute_ocean
+            .then(|| self.get_ocean_current(x, y, &primitive_elevation_factors, Some(&samples)));
+
+        let temperature_surface =
+            self.get_temperature_surface(x, y, ocean_current, Some(&samples), elevation);
+        let (ocean_current_angle, ocean_current_magnitude) = ocean_current
+            .map(|current| self.output_ocean_current(current))
+            .unzip();
+
+        // humidity is not modeled yet, so the continentalness stands in for the dryness
+        let diurnal_range = {
+            let dryness = if primitive_elevation_factors.elevation.normalized > 0.0 {
+                (1.0 + primitive_elevation_factors.shelf
+                    / (2.0 * self.params.primitive_shelf_depth))
+                    .clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            self.params.diurnal_range_base + self.params.diurnal_range_modulation * dryness
+        };
+
+        let (
+            sea_level_pressure_normalized,
+            atmosphere_pressure_normalized,
+            atmosphere_current_angle,
+            atmosphere_current_magnitude,
+            atmosphere_current_speed,
+        ) = if self.params.compute_atmosphere {
+            let (sea_level_pressure_normalized, pressure_gradient) =
+                self.atmosphere_pressure_value_and_gradient(x, y);
+            let atmosphere_pressure_normalized = self.reduce_pressure(
+                sea_level_pressure_normalized,
+                primitive_elevation_factors.elevation.value.max(0.0),
+            );
+
+            // the current follows the blurred pressure, keeping the jitter of the noise out
+            // (the gradient of the blur is the blur of the gradients)
+            let radius = self.params.pressure_smoothing_radius;
+            let (gx, gy) = if radius > 0.0 {
+                const WEIGHTS: [f64; 3] = [1.0, 2.0, 1.0];
+                let mut sum = (0.0, 0.0);
+                for (iy, wy) in WEIGHTS.iter().enumerate() {
+                    for (ix, wx) in WEIGHTS.iter().enumerate() {
+                        let (gx, gy) = if (ix, iy) == (1, 1) {
+                            pressure_gradient
+                        } else {
+                            let dx = (ix as f64 - 1.0) * radius;
+                            let dy = (iy as f64 - 1.0) * radius;
+                            self.atmosphere_pressure_value_and_gradient(x + dx, y + dy)
+                                .1
+                        };
+                        sum.0 += wx * wy * gx;
+                        sum.1 += wx * wy * gy;
+                    }
+                }
+                (sum.0 / 16.0, sum.1 / 16.0)
+            } else {
+                pressure_gradient
+            };
+            // same convention as get_gradient: the angle of descent and the (negative) slope
+            let (descent_angle, diff) = if gx.hypot(gy) < FLAT_GRADIENT_THRESHOLD {
+                (0.0, 0.0)
+            } else {
+                ((-gy).atan2(-gx), -gx.hypot(gy))
+            };
+
+            let (atmosphere_current_angle, atmosphere_current_magnitude) =
+                match self.params.circulation_model {
+                    CirculationModel::Heuristic => {
+                        let angle_offset = -(((y + 0.5) * std::f64::consts::PI).tan().abs()
+                            * (y * std::f64::consts::PI).sin())
+                        .atan();
+                        (descent_angle + angle_offset, diff / std::f64::consts::PI)
+                    }
+                    CirculationModel::Geostrophic => {
+                        let coriolis = latitude.sin();
+                        let sign = if coriolis < 0.0 { -1.0 } else { 1.0 };
+                        let coriolis = coriolis.abs().max(self.params.geostrophic_min_coriolis);
+                        // k x grad(p) is grad(p) turned by +PI/2 (reversed in the southern hemisphere)
+                        (
+                            descent_angle + std::f64::consts::FRAC_PI_2 * sign,
+                            diff / std::f64::consts::PI / coriolis,
+                        )
+                    }
+                };
+            let atmosphere_current_speed =
+                atmosphere_current_magnitude.abs() * self.params.wind_speed_scale;
+            (
+                Some(sea_level_pressure_normalized),
+                Some(atmosphere_pressure_normalized),
+                Some(atmosphere_current_angle),
+                Some(atmosphere_current_magnitude),
+                Some(atmosphere_current_speed),
+            )
+        } else {
+            (None, None, None, None, None)
+        };
+        EnvironmentFactors {
+            virtual_latitude: latitude,
+            temperature_surface,
+            diurnal_range,
+
+            sea_level_pressure_normalized,
+            atmosphere_pressure_normalized,
+            atmosphere_current_angle,
+            atmosphere_current_magnitude,
+            atmosphere_current_speed,
+
+            primitive_elevation_factors,
+            ocean_current_angle,
+            ocean_current_magnitude,
+        }
+    }
+}
+
+impl<N: NoiseSource> EnvironmentProvider for ReferenceEnvironmentProvider<N> {
+    fn get_parameters(&self) -> &ReferenceEnvironmentParameters {
+        &self.params
+    }
+
+    fn get_factors(&self, x: f64, y: f64) -> Option<EnvironmentFactors> {
+        let primitive_elevation_factors = self.get_valid_primitive_elevation_factors(x, y)?;
+        Some(
+            self.get_factors_with_elevation(x, y, primitive_elevation_factors, &|x, y| {
+                self.get_primitive_elevation_factors(x, y)
+            }),
+        )
+    }
+
+    fn elevation_at(&self, x: f64, y: f64) -> Option<ValueWithNormalized> {
+        Some(self.get_valid_primitive_elevation_factors(x, y)?.elevation)
+    }
+
+    fn temperature_at(&self, x: f64, y: f64) -> Option<f64> {
+        let primitive_elevation_factors = self.get_valid_primitive_elevation_factors(x, y)?;
+        let ocean_current = self
+            .params
+            .compute_ocean
+            .then(|| self.get_ocean_current(x, y, &primitive_elevation_factors, None));
+        Some(
+            self.get_temperature_surface(x, y, ocean_current, None, &|x, y| {
+                self.get_primitive_elevation_factors(x, y)
+            }),
+        )
+    }
+
+    fn current_at(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let primitive_elevation_factors = self.get_valid_primitive_elevation_factors(x, y)?;
+        if !self.params.compute_ocean {
+            return None;
+        }
+        Some(self.output_ocean_current(self.get_ocean_current(
+            x,
+            y,
+            &primitive_elevation_factors,
+            None,
+        )))
+    }
+}
+
+/// diff_factors of two providers on the grid sampling `region` every `resolution`
+/// (row-major; None where either provider is invalid)
+pub fn diff_grid(
+    a: &impl EnvironmentProvider,
+    b: &impl EnvironmentProvider,
+    region: Region,
+    resolution: f64,
+) -> Vec<Option<FactorDiff>> {
+    region
+        .grid_points(resolution)
+        .map(|(_, (x, y))| Some(diff_factors(&a.get_factors(x, y)?, &b.get_factors(x, y)?)))
+        .collect()
+}
+
+/// Samples at the point of get_factors reused by the derivation of several factors
+struct PointSamples {
+    /// virtual_latitude_fn at the point
+    virtual_latitude: f64,
+}
+
+/// Direction searched by the gradient search (see field_gradient_at)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GradientDirection {
+    /// Toward the minimum value around the point
+    Descent,
+    /// Toward the maximum value around the point
+    Ascent,
+}
+
+/// Why a downhill path ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DownhillEnd {
+    SeaLevel,
+    /// Flat spot or local minimum above the sea level
+    Sink,
+    Invalid,
+    MaxSteps,
+}
+
+/// Wraps a provider and memoizes get_factors (including invalid points) on a grid of
+/// `quantization` spacing, keeping at most `capacity` points (least recently used first out).
+///
+/// A query is answered with the factors of the nearest grid point.
+/// The cache is behind a mutex, so the wrapper is Sync if `P` is and can be shared between
+/// threads (the provider is queried outside the lock, so misses are computed in parallel).
+pub struct CachedEnvironmentProvider<P: EnvironmentProvider> {
+    provider: P,
+    quantization: f64,
+    capacity: usize,
+    cache: Mutex<FactorsCache>,
+}
+
+#[derive(Default)]
+struct FactorsCache {
+    /// quantized point -> (factors, last used tick)
+    entries: HashMap<(i64, i64), (Option<EnvironmentFactors>, u64)>,
+    /// last used tick -> quantized point
+    usage: BTreeMap<u64, (i64, i64)>,
+    tick: u64,
+}
+
+impl<P: EnvironmentProvider> CachedEnvironmentProvider<P> {
+    pub fn new(provider: P, quantization: f64, capacity: usize) -> Self {
+        Self {
+            provider,
+            quantization,
+            capacity,
+            cache: Mutex::new(FactorsCache::default()),
+        }
+    }
+
+    pub fn provider(&self) -> &P {
+        &self.provider
+    }
+
+    pub fn clear(&self) {
+        *self.lock_cache() = FactorsCache::default();
+    }
+
+    /// The cache stays consistent if a thread panics while holding it,
+    /// so a poisoned lock is recovered
+    fn lock_cache(&self) -> MutexGuard<'_, FactorsCache> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<P: EnvironmentProvider> EnvironmentProvider for CachedEnvironmentProvider<P> {
+    fn get_parameters(&self) -> &ReferenceEnvironmentParameters {
+        self.provider.get_parameters()
+    }
+
+    fn get_factors(&self, x: f64, y: f64) -> Option<EnvironmentFactors> {
+        let key = (
+            (x / self.quantization).round() as i64,
+            (y / self.quantization).round() as i64,
+        );
+        {
+            let mut cache = self.lock_cache();
+            cache.tick += 1;
+            let tick = cache.tick;
+            if let Some((factors, last_used)) = cache.entries.get_mut(&key) {
+                let (factors, last_used_prev) = (*factors, *last_used);
+                *last_used = tick;
+                cache.usage.remove(&last_used_prev);
+                cache.usage.insert(tick, key);
+                return factors;
+            }
+        }
+
+        let factors = self.provider.get_factors(
+            key.0 as f64 * self.quantization,
+            key.1 as f64 * self.quantization,
+        );
+        if self.capacity == 0 {
+            return factors;
+        }
+        let mut cache = self.lock_cache();
+        cache.tick += 1;
+        let tick = cache.tick;
+        // another thread may have computed the same point in the meantime
+        if let Some((_, last_used)) = cache.entries.remove(&key) {
+            cache.usage.remove(&last_used);
+        }
+        while cache.entries.len() >= self.capacity {
+            let Some((_, oldest)) = cache.usage.pop_first() else {
+                break;
+            };
+            cache.entries.remove(&oldest);
+        }
+        cache.entries.insert(key, (factors, tick));
+        cache.usage.insert(tick, key);
+        factors
+    }
+}
+
+/// [ReferenceEnvironmentProvider] with the elevation replaced by external data (e.g. a DEM).
+/// The climate factors (ocean current, diurnal range, pressure etc.) are derived from the
+/// external elevation, and valid_elevation_fn is applied to it; the other primitive
+/// elevation factors (shelf, persistence, land base) stay procedural.
+pub struct ExternalElevationProvider<N: NoiseSource = Simplex<2>> {
+    provider: ReferenceEnvironmentProvider<N>,
+    elevation_fn: Box<dyn Fn(f64, f64) -> Option<f64> + Send + Sync>,
+}
+
+impl<N: NoiseSource> ExternalElevationProvider<N> {
+    /// `elevation_fn`: (x, y) -> elevation (m), mapped onto the normalized elevation by
+    /// primitive_elevation_range (None: no data, regarded as invalid)
+    pub fn new(
+        provider: ReferenceEnvironmentProvider<N>,
+        elevation_fn: impl Fn(f64, f64) -> Option<f64> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            provider,
+            elevation_fn: Box::new(elevation_fn),
+        }
+    }
+
+    pub fn provider(&self) -> &ReferenceEnvironmentProvider<N> {
+        &self.provider
+    }
+
+    /// Primitive elevation factors at (x, y) with the external elevation
+    /// (the procedural elevation where elevation_fn has no data; the validity is not checked)
+    fn get_primitive_elevation_factors(&self, x: f64, y: f64) -> PrimitiveElevationFactors {
+        let primitive_elevation_factors = self.provider.get_primitive_elevation_factors(x, y);
+        match (self.elevation_fn)(x, y) {
+            Some(elevation) => PrimitiveElevationFactors {
+                elevation: ValueWithNormalized::from_signed_value(
+                    elevation,
+                    self.provider.get_parameters().primitive_elevation_range,
+                ),
+                ..primitive_elevation_factors
+            },
+            None => primitive_elevation_factors,
+        }
+    }
+}
+
+impl<N: NoiseSource> EnvironmentProvider for ExternalElevationProvider<N> {
+    fn get_parameters(&self) -> &ReferenceEnvironmentParameters {
+        self.provider.get_parameters()
+    }
+
+    fn get_factors(&self, x: f64, y: f64) -> Option<EnvironmentFactors> {
+        let elevation = self.elevation_at(x, y)?;
+        let primitive_elevation_factors = PrimitiveElevationFactors {
+            elevation,
+            ..self.provider.get_primitive_elevation_factors(x, y)
+        };
+        Some(self.provider.get_factors_with_elevation(
+            x,
+            y,
+            primitive_elevation_factors,
+            &|x, y| self.get_primitive_elevation_factors(x, y),
+        ))
+    }
+
+    fn elevation_at(&self, x: f64, y: f64) -> Option<ValueWithNormalized> {
+        let params = self.provider.get_parameters();
+        if !(params.valid_fn)(x, y) {
+            return None;
+        }
+        let elevation = ValueWithNormalized::from_signed_value(
+            (self.elevation_fn)(x, y)?,
+            params.primitive_elevation_range,
+        );
+        self.provider
+            .is_valid_elevation(x, y, &elevation)
+            .then_some(elevation)
+    }
+}
+
+/// [ReferenceEnvironmentProvider] with the continent given by a mask (e.g. a painted layout)
+/// instead of NOISE_PRIMITIVE_CONTINENT. The land detail, the shelf shaping and the climate
+/// factors stay procedural on top of the mask.
+pub struct ContinentMaskProvider<N: NoiseSource = Simplex<2>> {
+    provider: ReferenceEnvironmentProvider<N>,
+    mask_fn: Box<dyn Fn(f64, f64) -> Option<f64> + Send + Sync>,
+}
+
+impl<N: NoiseSource> ContinentMaskProvider<N> {
+    /// `mask_fn`: (x, y) -> grayscale [0.0, 1.0] (0.0: deep ocean, 1.0: the heart of a
+    /// continent), mapped onto the continent noise [-1.0, 1.0] (None: no data, regarded as
+    /// invalid)
+    pub fn new(
+        provider: ReferenceEnvironmentProvider<N>,
+        mask_fn: impl Fn(f64, f64) -> Option<f64> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            provider,
+            mask_fn: Box::new(mask_fn),
+        }
+    }
+
+    /// Mask of `cols` x `rows` grayscale values (row-major) spanning `region`
+    /// (the first value at (min_x, min_y), the last at (max_x, max_y)),
+    /// sampled bilinearly (None outside the region)
+    pub fn from_grid(
+        provider: ReferenceEnvironmentProvider<N>,
+        mask: Vec<f64>,
+        cols: usize,
+        rows: usize,
+        region: Region,
+    ) -> Self {
+        Self::new(provider, move |x, y| {
+            sample_grid_bilinear(&mask, cols, rows, region, x, y)
+        })
+    }
+
+    pub fn provider(&self) -> &ReferenceEnvironmentProvider<N> {
+        &self.provider
+    }
+
+    /// Primitive elevation factors at (x, y) on the mask, read as `fallback` where it has
+    /// no data (the validity is not checked)
+    fn get_primitive_elevation_factors(
+        &self,
+        x: f64,
+        y: f64,
+        fallback: f64,
+    ) -> PrimitiveElevationFactors {
+        let continent = |x: f64, y: f64| (self.mask_fn)(x, y).unwrap_or(fallback) * 2.0 - 1.0;
+        self.provider
+            .get_primitive_elevation_factors_with_continent(x, y, Some(&continent), 0)
+            .0
+    }
+
+    /// (primitive elevation factors, mask value) at (x, y) if the point has mask data and
+    /// passes valid_fn and valid_elevation_fn
+    fn get_valid_primitive_elevation_factors(
+        &self,
+        x: f64,
+        y: f64,
+    ) -> Option<(PrimitiveElevationFactors, f64)> {
+        if !(self.provider.get_parameters().valid_fn)(x, y) {
+            return None;
+        }
+        let center = (self.mask_fn)(x, y)?;
+        // the fjords may displace the sample off the mask
+        let primitive_elevation_factors = self.get_primitive_elevation_factors(x, y, center);
+        self.provider
+            .is_valid_elevation(x, y, &primitive_elevation_factors.elevation)
+            .then_some((primitive_elevation_factors, center))
+    }
+}
+
+impl<N: NoiseSource> EnvironmentProvider for ContinentMaskProvider<N> {
+    fn get_parameters(&self) -> &ReferenceEnvironmentParameters {
+        self.provider.get_parameters()
+    }
+
+    fn get_factors(&self, x: f64, y: f64) -> Option<EnvironmentFactors> {
+        let (primitive_elevation_factors, center) =
+            self.get_valid_primitive_elevation_factors(x, y)?;
+        // the upstream trace of the ocean current runs over the mask as well
+        Some(self.provider.get_factors_with_elevation(
+            x,
+            y,
+            primitive_elevation_factors,
+            &|x, y| self.get_primitive_elevation_factors(x, y, center),
+        ))
+    }
+
+    fn elevation_at(&self, x: f64, y: f64) -> Option<ValueWithNormalized> {
+        Some(
+            self.get_valid_primitive_elevation_factors(x, y)?
+                .0
+                .elevation,
+        )
+    }
+}
+
+/// Bilinear sample at (x, y) of `cols` x `rows` values (row-major) spanning `region`
+/// (None outside the region)
+fn sample_grid_bilinear(
+    values: &[f64],
+    cols: usize,
+    rows: usize,
+    region: Region,
+    x: f64,
+    y: f64,
+) -> Option<f64> {
+    if cols == 0 || rows == 0 || values.len() < cols * rows {
+        return None;
+    }
+    let gx = (x - region.min_x) / (region.max_x - region.min_x) * (cols - 1) as f64;
+    let gy = (y - region.min_y) / (region.max_y - region.min_y) * (rows - 1) as f64;
+    let (gx, gy) = (
+        if cols == 1 { 0.0 } else { gx },
+        if rows == 1 { 0.0 } else { gy },
+    );
+    if !(gx >= 0.0 && gy >= 0.0 && gx <= (cols - 1) as f64 && gy <= (rows - 1) as f64) {
+        return None;
+    }
+    let ix = (gx.floor() as usize).min(cols.saturating_sub(2));
+    let iy = (gy.floor() as usize).min(rows.saturating_sub(2));
+    let (tx, ty) = (gx - ix as f64, gy - iy as f64);
+    let at = |i: usize, j: usize| values[(iy + j).min(rows - 1) * cols + (ix + i).min(cols - 1)];
+    Some(
+        (at(0, 0) * (1.0 - tx) + at(1, 0) * tx) * (1.0 - ty)
+            + (at(0, 1) * (1.0 - tx) + at(1, 1) * tx) * ty,
+    )
+}
+
+/// ln(1 + exp(x)), without overflow for large x
+fn softplus(x: f64) -> f64 {
+    if x > 30.0 {
+        x
+    } else {
+        x.exp().ln_1p()
+    }
+}
+
+fn rotate(x: f64, y: f64, angle: f64) -> (f64, f64) {
+    let (sin, cos) = angle.sin_cos();
+    (x * cos - y * sin, x * sin + y * cos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    const REGION: Region = Region {
+        min_x: -1.0,
+        min_y: -0.5,
+        max_x: 1.0,
+        max_y: 0.5,
+    };
+
+    #[test]
+    fn contours_of_a_linear_field_are_parallel_lines() {
+        let provider = flat_provider(params_with_latitude(|x, _| x));
+        let thresholds = [-0.55, 0.05, 0.45];
+        let contours = provider.contours(|f| f.virtual_latitude, &thresholds, REGION, 0.1);
+        assert_eq!(contours.len(), thresholds.len());
+        for (contour, &threshold) in contours.iter().zip(&thresholds) {
+            assert_eq!(contour.threshold, threshold);
+            assert_eq!(contour.lines.len(), 1);
+            let line = &contour.lines[0];
+            for &(x, _) in line {
+                assert!(
+                    (x - threshold).abs() < 1e-9,
+                    "{x} off the isoline {threshold}"
+                );
+            }
+            let (min_y, max_y) = line
+                .iter()
+                .fold((f64::MAX, f64::MIN), |(min, max), &(_, y)| {
+                    (min.min(y), max.max(y))
+                });
+            assert!((min_y - REGION.min_y).abs() < 1e-9 && (max_y - REGION.max_y).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn find_peaks_finds_the_summit_of_a_single_peak() {
+        let params = ReferenceEnvironmentParameters {
+            valid_fn: ValidModel::Everywhere.into_fn(),
+            ..Default::default()
+        };
+        let provider = land_provider(|x, y| (-(x * x + y * y) * 4.0).exp(), params);
+        let region = Region {
+            min_x: -1.0,
+            min_y: -1.0,
+            max_x: 1.0,
+            max_y: 1.0,
+        };
+        let peaks = provider.find_peaks(region, 0.05, 100.0);
+        assert_eq!(peaks.len(), 1);
+        let ((x, y), elevation) = peaks[0];
+        assert!(x.hypot(y) < 0.05, "summit at ({x}, {y})");
+        assert_eq!(
+            Some(elevation),
+            provider.elevation_at(x, y).map(|e| e.value)
+        );
+    }
+
+    #[test]
+    fn flat_fields_have_no_gradient_direction() {
+        // the noise is 0.0 everywhere, so the pressure is flat without the latitudinal base
+        let params = ReferenceEnvironmentParameters {
+            atmosphere_pressure_noise_prop: 1.0,
+            ..params_with_latitude(|_, y| y)
+        };
+        let provider = flat_provider(params);
+        for (_, (x, y)) in REGION.grid_points(0.25) {
+            let factors = provider.get_factors(x, y).unwrap();
+            assert_eq!(factors.atmosphere_current_magnitude, Some(0.0));
+            assert!(factors
+                .atmosphere_current_angle
+                .is_some_and(|angle| angle.is_finite()));
+            assert!(factors.to_feature_array().iter().all(|v| v.is_finite()));
+            for direction in [GradientDirection::Descent, GradientDirection::Ascent] {
+                let gradient =
+                    provider.field_gradient_at(x, y, 0.01, |f| f.temperature_surface, direction);
+                // the temperature only depends on the latitude (y), so the gradient is not flat
+                assert!(gradient.unwrap().1 != 0.0);
+                let gradient = provider.field_gradient_at(x, y, 0.01, |_| 1.0, direction);
+                assert_eq!(gradient, Some((0.0, 0.0)));
+            }
+        }
+    }
+
+    #[test]
+    fn gradient_search_finds_opposite_descent_and_ascent() {
+        let provider = flat_provider(params_with_latitude(|_, y| y));
+        let field = |f: &EnvironmentFactors| f.virtual_latitude;
+        let (descent, slope) = provider
+            .field_gradient_at(0.1, 0.2, 0.01, field, GradientDirection::Descent)
+            .unwrap();
+        let (ascent, rise) = provider
+            .field_gradient_at(0.1, 0.2, 0.01, field, GradientDirection::Ascent)
+            .unwrap();
+        assert!(angle_difference(descent, -std::f64::consts::FRAC_PI_2).abs() < 0.05);
+        assert!(angle_difference(ascent, std::f64::consts::FRAC_PI_2).abs() < 0.05);
+        assert!((slope + 1.0).abs() < 0.01 && (rise - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn disabled_layers_are_none() {
+        let params = ReferenceEnvironmentParameters {
+            compute_atmosphere: false,
+            compute_ocean: false,
+            ..params_with_latitude(|_, y| y)
+        };
+        let provider = flat_provider(params);
+        let factors = provider.get_factors(0.1, 0.2).unwrap();
+        assert_eq!(factors.sea_level_pressure_normalized, None);
+        assert_eq!(factors.atmosphere_pressure_normalized, None);
+        assert_eq!(factors.atmosphere_current_angle, None);
+        assert_eq!(factors.atmosphere_current_magnitude, None);
+        assert_eq!(factors.atmosphere_current_speed, None);
+        assert_eq!(factors.ocean_current_angle, None);
+        assert_eq!(factors.ocean_current_magnitude, None);
+        assert_eq!(factors.get_vector(FactorLayer::AtmosphereCurrent), None);
+        assert_eq!(
+            factors.get_scalar(FactorLayer::AtmosphereCurrentSpeed),
+            None
+        );
+        assert_eq!(provider.current_at(0.1, 0.2), None);
+        assert!(provider.get_factors(0.1, 0.2).is_some());
+        let features = factors.to_feature_array();
+        for (name, value) in FEATURE_NAMES.iter().zip(features) {
+            let computed = !name.starts_with("atmosphere")
+                && !name.starts_with("sea_level_pressure")
+                && !name.starts_with("ocean");
+            assert_eq!(value.is_finite(), computed, "{name}");
+        }
+    }
+
+    #[test]
+    fn disabled_layers_sample_no_noise() {
+        let samples_per_point = |compute_atmosphere: bool, compute_ocean: bool| {
+            let noise = CountingNoise::default();
+            let params = ReferenceEnvironmentParameters {
+                compute_atmosphere,
+                compute_ocean,
+                ..params_with_latitude(|_, y| y)
+            };
+            let provider = provider_with(noise.clone(), params);
+            provider.elevation_at(0.1, 0.2).unwrap();
+            let elevation = noise.take_count();
+            provider.get_factors(0.1, 0.2).unwrap();
+            (elevation, noise.take_count())
+        };
+        let (elevation, none) = samples_per_point(false, false);
+        // only the elevation is sampled
+        assert_eq!(none, elevation);
+        let (_, atmosphere) = samples_per_point(true, false);
+        let (_, ocean) = samples_per_point(false, true);
+        let (_, both) = samples_per_point(true, true);
+        assert!(atmosphere > none && ocean > none);
+        assert_eq!(both - none, (atmosphere - none) + (ocean - none));
+    }
+
+    #[test]
+    fn sea_level_is_at_zero_meters() {
+        let provider = flat_provider(params_with_latitude(|_, y| y));
+        let elevation = provider.elevation_at(0.0, 0.0).unwrap();
+        // the flat ocean floor lies at -primitive_shelf_depth
+        assert_eq!(elevation.normalized, -0.3);
+        assert!((elevation.value - -1500.0).abs() < 1e-9);
+
+        let range = provider.get_parameters().primitive_elevation_range;
+        let sea_level = ValueWithNormalized::from_signed_normalized(0.0, range);
+        assert_eq!(sea_level.value, 0.0);
+        assert_eq!(
+            ValueWithNormalized::from_signed_value(0.0, range).normalized,
+            0.0
+        );
+    }
+
+    #[test]
+    fn elevation_stays_within_its_range() {
+        for primitive_abyssal_depth in [None, Some(0.5)] {
+            let params = ReferenceEnvironmentParameters {
+                primitive_shelf_depth: 2.0,
+                primitive_abyssal_depth,
+                land_elevation_scale: 3.0,
+                ..params_with_latitude(|_, y| y)
+            };
+            let range = params.primitive_elevation_range;
+            let provider = provider_with(WaveNoise::default(), params);
+            let elevations = REGION
+                .grid_points(0.05)
+                .map(|(_, (x, y))| provider.elevation_at(x, y).unwrap())
+                .collect::<Vec<_>>();
+            let lowest = primitive_abyssal_depth.map_or(range.min, |depth| {
+                ValueWithNormalized::from_signed_normalized(-depth, range).value
+            });
+            for elevation in &elevations {
+                assert!(elevation.value >= lowest - 1e-9 && elevation.value <= range.max);
+            }
+            // the deep shelf reaches the floor
+            assert!(elevations.iter().any(|e| (e.value - lowest).abs() < 1e-9));
+        }
+    }
+
+    #[test]
+    fn pressure_never_increases_with_elevation() {
+        for (offset, blend) in [
+            (-0.5, PressureNoiseBlend::Signed),
+            (0.5, PressureNoiseBlend::Signed),
+            (-0.5, PressureNoiseBlend::Unsigned),
+        ] {
+            // the pressure is the noise alone, uniform at any latitude
+            let params = ReferenceEnvironmentParameters {
+                atmosphere_pressure_noise_prop: 1.0,
+                atmosphere_pressure_noise_blend: blend,
+                ..params_with_latitude(|_, y| y)
+            };
+            let noise = PlaneNoise {
+                offset,
+                ..Default::default()
+            };
+            let provider = ExternalElevationProvider::new(provider_with(noise, params), |x, _| {
+                Some(x * 5000.0)
+            });
+            let pressures = (0..=20)
+                .map(|i| {
+                    let factors = provider.get_factors(i as f64 / 20.0, 0.3).unwrap();
+                    (
+                        factors.sea_level_pressure_normalized.unwrap(),
+                        factors.atmosphere_pressure_normalized.unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let sea_level = pressures[0].0;
+            assert_eq!(pressures[0].1, sea_level);
+            for pair in pressures.windows(2) {
+                assert_eq!(pair[1].0, sea_level);
+                assert!(pair[1].1 < pair[0].1, "{blend:?} {offset}: {pressures:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn vertical_profile_decreases_with_altitude() {
+        let altitudes = [0.0, 500.0, 1000.0, 3000.0, 8000.0, 20000.0];
+        // negative and positive sea level pressures
+        for offset in [-0.5, 0.5] {
+            let params = ReferenceEnvironmentParameters {
+                atmosphere_pressure_noise_prop: 1.0,
+                ..params_with_latitude(|_, y| y)
+            };
+            let noise = PlaneNoise {
+                offset,
+                ..Default::default()
+            };
+            let provider = provider_with(noise, params);
+            let factors = provider.get_factors(0.1, 0.2).unwrap();
+            let profile = provider.vertical_profile(0.1, 0.2, &altitudes).unwrap();
+            assert_eq!(profile.len(), altitudes.len());
+            assert_eq!(
+                profile[0].pressure_normalized,
+                factors.sea_level_pressure_normalized
+            );
+            for pair in profile.windows(2) {
+                assert!(pair[1].temperature < pair[0].temperature);
+                assert!(
+                    pair[1].pressure_normalized.unwrap() < pair[0].pressure_normalized.unwrap()
+                );
+            }
+        }
+    }
+
+    /// (estimated, actually taken) noise samples of get_factors on the grid of REGION
+    fn estimated_and_counted_noise_samples(params: ReferenceEnvironmentParameters) -> (u64, u64) {
+        let noise = CountingNoise::default();
+        let params = ReferenceEnvironmentParameters {
+            valid_fn: ValidModel::Everywhere.into_fn(),
+            ..params
+        };
+        let provider = provider_with(noise.clone(), params);
+        let estimate = provider.estimate_cost(REGION, 0.25);
+        assert_eq!(estimate.points, REGION.grid_points(0.25).count() as u64);
+        for (_, (x, y)) in REGION.grid_points(0.25) {
+            provider.get_factors(x, y).unwrap();
+        }
+        (estimate.noise_samples, noise.take_count() as u64)
+    }
+
+    #[test]
+    fn estimate_cost_matches_the_noise_samples() {
+        let configurations = [
+            ReferenceEnvironmentParameters::default(),
+            ReferenceEnvironmentParameters {
+                fjord_strength: 0.5,
+                ocean_floor_scale: Some(0.5),
+                ..Default::default()
+            },
+            ReferenceEnvironmentParameters {
+                ocean_current_detail_weight: 0.5,
+                ocean_heat_transport_steps: 3,
+                temperature_noise_prop: 0.5,
+                ..Default::default()
+            },
+            ReferenceEnvironmentParameters {
+                compute_atmosphere: false,
+                ..Default::default()
+            },
+            ReferenceEnvironmentParameters {
+                pressure_smoothing_radius: 0.01,
+                ..Default::default()
+            },
+        ];
+        for params in configurations {
+            let (estimated, counted) = estimated_and_counted_noise_samples(params);
+            assert_eq!(estimated, counted);
+        }
+    }
+
+    #[test]
+    fn shared_samples_keep_the_factors_identical() {
+        let provider = provider_with(WaveNoise::default(), params_with_latitude(|_, y| y));
+        for (_, (x, y)) in REGION.grid_points(0.25) {
+            let factors = provider.get_factors(x, y).unwrap();
+            // temperature_at and current_at sample everything themselves
+            assert_eq!(
+                provider.temperature_at(x, y),
+                Some(factors.temperature_surface)
+            );
+            assert_eq!(
+                provider.current_at(x, y),
+                factors.get_vector(FactorLayer::OceanCurrent)
+            );
+        }
+    }
+
+    /// Mean angular difference of the atmosphere current between adjacent samples along x
+    /// of a smooth pressure slope with a jitter of the wavelength 2 * `jitter_spacing`
+    fn mean_adjacent_current_turn(pressure_smoothing_radius: f64, jitter_spacing: f64) -> f64 {
+        let frequency = std::f64::consts::PI / jitter_spacing;
+        let provider = provider_with_channels(
+            vec![(
+                NOISE_ATMOSPHERE_PRESSURE,
+                FnNoise(Box::new(move |x, y| {
+                    0.5 * x + 0.05 * ((x * frequency).sin() + (y * frequency).sin())
+                })),
+            )],
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                pressure_smoothing_radius,
+                ..Default::default()
+            },
+        );
+        let angles = (0..50)
+            .map(|i| {
+                provider
+                    .get_factors(-0.2 + i as f64 * 0.0071, 0.2)
+                    .unwrap()
+                    .atmosphere_current_angle
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let turns = angles.windows(2).map(|pair| {
+            let turn = (pair[1] - pair[0]).rem_euclid(std::f64::consts::TAU);
+            turn.min(std::f64::consts::TAU - turn)
+        });
+        turns.sum::<f64>() / (angles.len() - 1) as f64
+    }
+
+    #[test]
+    fn smoothing_reduces_the_angular_variance_of_the_current() {
+        let jittery = mean_adjacent_current_turn(0.0, 0.01);
+        let smoothed = mean_adjacent_current_turn(0.01, 0.01);
+        assert!(jittery > 0.1, "{jittery}");
+        assert!(smoothed < jittery * 0.25, "{smoothed} vs {jittery}");
+    }
+
+    /// Mean |second difference| of the elevation along a line, with the land of WaveNoise
+    fn elevation_roughness(primitive_land_octave_amplitudes: Option<Vec<f64>>) -> f64 {
+        let provider = land_provider(
+            |x, y| WaveNoise::default().sample(x, y),
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                primitive_land_octave_amplitudes,
+                ..Default::default()
+            },
+        );
+        let elevations = (0..200)
+            .map(|i| {
+                let factors = provider.get_factors(-0.5 + i as f64 * 0.005, 0.1).unwrap();
+                factors.primitive_elevation_factors.elevation.normalized
+            })
+            .collect::<Vec<_>>();
+        let differences = elevations
+            .windows(3)
+            .map(|window| (window[0] - 2.0 * window[1] + window[2]).abs());
+        differences.sum::<f64>() / (elevations.len() - 2) as f64
+    }
+
+    #[test]
+    fn flat_octave_amplitudes_are_rougher_than_the_geometric_falloff() {
+        let geometric = elevation_roughness(None);
+        let flat = elevation_roughness(Some(vec![1.0; PRIMITIVE_LAND_OCTAVES]));
+        assert!(flat > geometric * 2.0, "{flat} vs {geometric}");
+    }
+
+    #[test]
+    fn surface_normals_follow_the_slope() {
+        assert_eq!(
+            flat_provider(ReferenceEnvironmentParameters::default()).surface_normal_at(0.1, 0.2),
+            Some((0.0, 0.0, 1.0))
+        );
+        // a single peak at the origin, whose flank at +x faces +x
+        let provider = land_provider(
+            |x, y| (-(x * x + y * y) * 4.0).exp(),
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        );
+        let (nx, ny, nz) = provider.surface_normal_at(0.02, 0.0).unwrap();
+        assert!(nx > 0.0 && nz > 0.0, "{nx} {nz}");
+        assert!(ny.abs() < 1e-9, "{ny}");
+        assert!(((nx * nx + ny * ny + nz * nz).sqrt() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn normal_map_of_a_flat_region_is_the_standard_blue() {
+        let provider = flat_provider(ReferenceEnvironmentParameters::default());
+        let path = std::env::temp_dir().join(format!(
+            "environment_builder_normal_map_{}.png",
+            std::process::id()
+        ));
+        provider.export_normal_map_png(REGION, 0.25, &path).unwrap();
+        let png = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (width, height) = REGION.grid_size(0.25);
+        let pixels = [128, 128, 255, 255].repeat(width * height);
+        assert_eq!(png, crate::png::encode_rgba(width, height, &pixels));
+    }
+
+    #[test]
+    fn area_breakdown_takes_a_normalized_sea_level() {
+        let provider = land_provider(
+            |x, y| (-(x * x + y * y) * 4.0).exp(),
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        );
+        let cell_area = (0.25 * provider.params.coordinate_meters_per_unit).powi(2);
+        let points = REGION.grid_points(0.25).count() as f64;
+        let land_points = REGION
+            .grid_points(0.25)
+            .filter(|&(_, (x, y))| provider.elevation_at(x, y).unwrap().normalized >= 0.5)
+            .count() as f64;
+        assert!(land_points > 0.0 && land_points < points);
+
+        let area = provider.area_breakdown(REGION, 0.25, 0.5);
+        assert_eq!(area.land_area, land_points * cell_area);
+        assert_eq!(area.ocean_area, (points - land_points) * cell_area);
+        let area = provider.area_breakdown(REGION, 0.25, -1.0);
+        assert_eq!(area.land_area, points * cell_area);
+    }
+
+    /// Provider valid on x in [10.0, 12.0) with the given edge mode, wrapping over that span
+    fn edge_provider(edge_mode: EdgeMode) -> ReferenceEnvironmentProvider<PlaneNoise> {
+        flat_provider(ReferenceEnvironmentParameters {
+            valid_fn: Box::new(|x, _| (10.0..12.0).contains(&x)),
+            edge_mode,
+            x_wrap_period: 2.0,
+            x_wrap_min: 10.0,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn edge_modes_treat_the_samples_outside_the_valid_area() {
+        let field = |x: f64, _: f64| x;
+        let sample =
+            |edge_mode, x| edge_provider(edge_mode).sample_for_gradient(x, 0.0, -1.0, &field);
+        assert_eq!(sample(EdgeMode::Extend, 11.0), Some(11.0));
+        assert_eq!(sample(EdgeMode::Extend, 12.5), Some(12.5));
+        assert_eq!(sample(EdgeMode::Clamp, 12.5), Some(-1.0));
+        assert_eq!(sample(EdgeMode::Skip, 12.5), None);
+        // wrapped across both seams into [10.0, 12.0)
+        assert_eq!(sample(EdgeMode::Wrap, 12.5), Some(10.5));
+        assert_eq!(sample(EdgeMode::Wrap, 9.5), Some(11.5));
+        assert_eq!(sample(EdgeMode::Wrap, 15.5), Some(11.5));
+    }
+
+    #[test]
+    fn wrapped_gradient_is_continuous_across_the_seam() {
+        // periodic over the wrapped span, so the wrapped samples continue the field
+        let field = |x: f64, _: f64| (x * std::f64::consts::PI).sin();
+        let wrapped = edge_provider(EdgeMode::Wrap);
+        let unbounded = flat_provider(ReferenceEnvironmentParameters {
+            valid_fn: ValidModel::Everywhere.into_fn(),
+            ..Default::default()
+        });
+        for x in [10.0, 10.01, 10.03, 11.97, 11.99, 11.999] {
+            let direction = GradientDirection::Descent;
+            let (angle, change) =
+                wrapped.get_gradient(x, 0.0, field(x, 0.0), 0.05, field, direction);
+            let (expected_angle, expected_change) =
+                unbounded.get_gradient(x, 0.0, field(x, 0.0), 0.05, field, direction);
+            assert!(
+                angle_difference(angle, expected_angle).abs() < 1e-9,
+                "at {x}"
+            );
+            assert!((change - expected_change).abs() < 1e-9, "at {x}");
+        }
+    }
+
+    #[test]
+    fn atmosphere_current_descends_the_pressure_gradient() {
+        let provider = provider_with(
+            WaveNoise::default(),
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        );
+        let pressure = |x: f64, y: f64| {
+            let factors = provider.get_factors(x, y).unwrap();
+            factors.sea_level_pressure_normalized.unwrap()
+        };
+        let h = 1e-6;
+        // on the equator, the heuristic circulation does not deflect the descent
+        for x in [-0.7, -0.2, 0.1, 0.4, 0.9] {
+            let gx = (pressure(x + h, 0.0) - pressure(x - h, 0.0)) / (2.0 * h);
+            let gy = (pressure(x, h) - pressure(x, -h)) / (2.0 * h);
+            let factors = provider.get_factors(x, 0.0).unwrap();
+            let angle = factors.atmosphere_current_angle.unwrap();
+            let magnitude = factors.atmosphere_current_magnitude.unwrap();
+            assert!(
+                angle_difference(angle, (-gy).atan2(-gx)).abs() < 1e-6,
+                "at {x}"
+            );
+            let expected = -gx.hypot(gy) / std::f64::consts::PI;
+            assert!(
+                (magnitude - expected).abs() < 1e-6 * expected.abs(),
+                "at {x}"
+            );
+        }
+    }
+
+    #[test]
+    fn world_gradient_follows_the_noise_domain() {
+        let gradient = (0.3, -0.8);
+        let shifted = flat_provider(ReferenceEnvironmentParameters {
+            noise_origin: (12.5, -3.0),
+            ..Default::default()
+        });
+        assert_eq!(shifted.world_gradient(0.2, 0.1, gradient), gradient);
+
+        let rotated = flat_provider(ReferenceEnvironmentParameters {
+            domain_rotation: 0.6,
+            ..Default::default()
+        });
+        // the world gradient is the noise gradient turned back by the rotation
+        let (gx, gy) = rotated.world_gradient(0.2, 0.1, gradient);
+        let (ex, ey) = rotate(gradient.0, gradient.1, -0.6);
+        assert!(
+            (gx - ex).abs() < 1e-6 && (gy - ey).abs() < 1e-6,
+            "{gx} {gy}"
+        );
+    }
+
+    #[test]
+    fn polar_convergence_reduces_the_x_frequency_toward_the_poles() {
+        let provider = flat_provider(ReferenceEnvironmentParameters {
+            polar_convergence: true,
+            ..params_with_latitude(|_, y| y)
+        });
+        // change of the noise x per unit of the world x
+        let x_frequency =
+            |y: f64| provider.noise_domain(1.0, y).0 - provider.noise_domain(0.0, y).0;
+        assert!((x_frequency(0.0) - 1.0).abs() < 1e-12);
+        assert!((x_frequency(1.2) - 1.2_f64.cos()).abs() < 1e-12);
+        assert!(x_frequency(1.2) < x_frequency(0.6) && x_frequency(0.6) < x_frequency(0.0));
+    }
+
+    #[test]
+    fn valid_elevation_fn_excludes_the_deep_ocean() {
+        // the continent falls from the land at +x to the deep ocean at -x
+        let provider = provider_with_channels(
+            vec![(
+                NOISE_PRIMITIVE_CONTINENT,
+                FnNoise(Box::new(|x, _| x.clamp(-1.0, 1.0))),
+            )],
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                valid_elevation_fn: Some(Box::new(|_, _, elevation| elevation.normalized > -0.5)),
+                ..Default::default()
+            },
+        );
+        let (shallow, deep) = REGION
+            .grid_points(0.25)
+            .map(|(_, (x, y))| (x, y))
+            .partition::<Vec<_>, _>(|&(x, y)| {
+                provider
+                    .get_primitive_elevation_factors(x, y)
+                    .elevation
+                    .normalized
+                    > -0.5
+            });
+        assert!(!shallow.is_empty() && !deep.is_empty());
+        for (x, y) in shallow {
+            assert!(provider.get_factors(x, y).is_some());
+        }
+        for (x, y) in deep {
+            assert!((provider.params.valid_fn)(x, y));
+            assert!(provider.get_factors(x, y).is_none());
+            assert!(provider.elevation_at(x, y).is_none());
+        }
+    }
+
+    /// Provider implementing only the required methods, so the defaults of the trait are used
+    struct FactorsOnly<P>(P);
+
+    impl<P: EnvironmentProvider> EnvironmentProvider for FactorsOnly<P> {
+        fn get_parameters(&self) -> &ReferenceEnvironmentParameters {
+            self.0.get_parameters()
+        }
+
+        fn get_factors(&self, x: f64, y: f64) -> Option<EnvironmentFactors> {
+            self.0.get_factors(x, y)
+        }
+    }
+
+    /// (elevation (m), temperature, ocean current)
+    type SingleFactors = (f64, f64, Option<(f64, f64)>);
+
+    /// SingleFactors through the single-factor methods
+    fn single_factors(
+        provider: &impl EnvironmentProvider,
+        x: f64,
+        y: f64,
+    ) -> Option<SingleFactors> {
+        Some((
+            provider.elevation_at(x, y)?.value,
+            provider.temperature_at(x, y)?,
+            provider.current_at(x, y),
+        ))
+    }
+
+    #[test]
+    fn single_factor_methods_agree_with_their_defaults() {
+        let provider = provider_with(
+            WaveNoise::default(),
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        );
+        for (_, (x, y)) in REGION.grid_points(0.25) {
+            let factors = provider.get_factors(x, y).unwrap();
+            let expected = (
+                factors.primitive_elevation_factors.elevation.value,
+                factors.temperature_surface,
+                factors.get_vector(FactorLayer::OceanCurrent),
+            );
+            assert_eq!(single_factors(&provider, x, y), Some(expected));
+            assert_eq!(
+                single_factors(&FactorsOnly(&provider), x, y),
+                Some(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn land_shaping_fn_replaces_the_power_curve() {
+        let elevations = |params: ReferenceEnvironmentParameters| {
+            let provider = land_provider(
+                |x, y| WaveNoise::default().sample(x, y),
+                ReferenceEnvironmentParameters {
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    ..params
+                },
+            );
+            REGION
+                .grid_points(0.25)
+                .map(|(_, (x, y))| provider.elevation_at(x, y).unwrap().normalized)
+                .collect::<Vec<_>>()
+        };
+        let identity = elevations(ReferenceEnvironmentParameters {
+            land_shaping_fn: Some(Box::new(|elevation| elevation)),
+            ..Default::default()
+        });
+        let linear = elevations(ReferenceEnvironmentParameters {
+            primitive_land_power: 1.0,
+            ..Default::default()
+        });
+        let squared = elevations(ReferenceEnvironmentParameters::default());
+        assert_eq!(identity, linear);
+        // the default power 2.0 lowers the land below 1.0
+        assert!(identity.iter().zip(&squared).all(|(a, b)| a >= b));
+        assert!(identity.iter().zip(&squared).any(|(a, b)| a > b));
+    }
+
+    #[test]
+    fn wind_speed_scales_with_the_magnitude_and_the_configured_scale() {
+        let speeds = |wind_speed_scale: f64| {
+            let provider = provider_with(
+                WaveNoise::default(),
+                ReferenceEnvironmentParameters {
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    wind_speed_scale,
+                    ..Default::default()
+                },
+            );
+            REGION
+                .grid_points(0.25)
+                .map(|(_, (x, y))| {
+                    let factors = provider.get_factors(x, y).unwrap();
+                    let magnitude = factors.atmosphere_current_magnitude.unwrap();
+                    (magnitude, factors.wind_speed_ms().unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+        let (speeds, doubled) = (speeds(20.0), speeds(40.0));
+        assert!(speeds.iter().any(|&(magnitude, _)| magnitude != 0.0));
+        for (&(magnitude, speed), &(_, doubled)) in speeds.iter().zip(&doubled) {
+            assert!((speed - magnitude.abs() * 20.0).abs() < 1e-12);
+            assert!((doubled - 2.0 * speed).abs() < 1e-12);
+        }
+    }
+
+    /// Provider counting the calls of get_factors of the wrapped provider
+    struct CountingProvider<P> {
+        provider: P,
+        calls: std::cell::Cell<usize>,
+    }
+
+    impl<P: EnvironmentProvider> EnvironmentProvider for CountingProvider<P> {
+        fn get_parameters(&self) -> &ReferenceEnvironmentParameters {
+            self.provider.get_parameters()
+        }
+
+        fn get_factors(&self, x: f64, y: f64) -> Option<EnvironmentFactors> {
+            self.calls.set(self.calls.get() + 1);
+            self.provider.get_factors(x, y)
+        }
+    }
+
+    #[test]
+    fn cached_provider_calls_the_provider_once_per_quantized_point() {
+        let counting = CountingProvider {
+            provider: provider_with(
+                WaveNoise::default(),
+                ReferenceEnvironmentParameters::default(),
+            ),
+            calls: std::cell::Cell::new(0),
+        };
+        let cached = CachedEnvironmentProvider::new(&counting, 0.01, 2);
+        let first = cached.get_factors(0.301, 0.2).unwrap();
+        let second = cached.get_factors(0.299, 0.2).unwrap();
+        assert_eq!(counting.calls.get(), 1);
+        assert!(factors_approx_eq(
+            &first,
+            &second,
+            &FactorTolerance::default()
+        ));
+        assert!(factors_approx_eq(
+            &first,
+            &counting.provider.get_factors(0.3, 0.2).unwrap(),
+            &FactorTolerance::default()
+        ));
+
+        // (0.3, 0.2) is used more recently than (0.5, 0.2), so (0.5, 0.2) is evicted
+        cached.get_factors(0.5, 0.2);
+        cached.get_factors(0.3, 0.2);
+        cached.get_factors(0.7, 0.2);
+        assert_eq!(counting.calls.get(), 3);
+        cached.get_factors(0.3, 0.2);
+        assert_eq!(counting.calls.get(), 3);
+        cached.get_factors(0.5, 0.2);
+        assert_eq!(counting.calls.get(), 4);
+    }
+
+    #[test]
+    fn fjords_lengthen_the_coastline() {
+        let coastline_length = |fjord_strength: f64| {
+            let provider = provider_with(
+                WaveNoise {
+                    frequency: 7.0,
+                    amplitude: 1.0,
+                },
+                ReferenceEnvironmentParameters {
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    fjord_strength,
+                    ..Default::default()
+                },
+            );
+            provider.coastline_length(REGION, 0.01, 0.0)
+        };
+        let (plain, fjords) = (coastline_length(0.0), coastline_length(1.0));
+        assert!(plain > 0.0);
+        assert!(fjords > plain * 1.1, "{fjords} vs {plain}");
+    }
+
+    #[test]
+    fn reduce_region_counts_the_land_cells() {
+        // the valid band |y| < 1.0 cuts the region
+        let region = Region {
+            min_x: -1.0,
+            min_y: -1.5,
+            max_x: 1.0,
+            max_y: 1.5,
+        };
+        let provider = provider_with(
+            WaveNoise::default(),
+            ReferenceEnvironmentParameters::default(),
+        );
+        let (land, total) =
+            provider.reduce_region(region, 0.1, (0, 0), |(land, total), factors| {
+                let is_land = factors.primitive_elevation_factors.elevation.normalized >= 0.0;
+                (land + is_land as usize, total + 1)
+            });
+        let valid = region
+            .grid_points(0.1)
+            .filter(|&(_, (_, y))| y.abs() < 1.0)
+            .count();
+        assert_eq!(total, valid);
+        assert!(land > 0 && land < total);
+
+        let area = provider.area_breakdown(region, 0.1, 0.0);
+        let land_fraction = area.land_area / (area.land_area + area.ocean_area);
+        assert!((land as f64 / total as f64 - land_fraction).abs() < 1e-12);
+    }
+
+    #[test]
+    fn downhill_path_on_a_planar_slope_is_a_straight_line() {
+        // the land rises along (1, 2)
+        let provider = land_provider(
+            |x, y| 0.5 + 0.0005 * (x + 2.0 * y),
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        );
+        let path = provider.trace_downhill(0.1, 0.2, 20, 0.01);
+        assert_eq!(path.len(), 21);
+        let (ex, ey) = (-1.0 / 5.0_f64.sqrt(), -2.0 / 5.0_f64.sqrt());
+        for step in path.windows(2) {
+            let (dx, dy) = (step[1].0 - step[0].0, step[1].1 - step[0].1);
+            assert!(
+                (dx - ex * 0.01).abs() < 1e-6 && (dy - ey * 0.01).abs() < 1e-6,
+                "{dx} {dy}"
+            );
+        }
+    }
+
+    #[test]
+    fn octave_rotation_changes_only_the_rotated_octaves() {
+        let elevations = |octave_rotation: f64| {
+            let provider = provider_with(
+                WaveNoise::default(),
+                ReferenceEnvironmentParameters {
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    octave_rotation,
+                    ..Default::default()
+                },
+            );
+            REGION
+                .grid_points(0.25)
+                .map(|(_, (x, y))| provider.elevation_at(x, y).unwrap().value)
+                .collect::<Vec<_>>()
+        };
+        // the default has no rotation (the goldens guard its output)
+        assert_eq!(
+            ReferenceEnvironmentParameters::default().octave_rotation,
+            0.0
+        );
+        assert_ne!(elevations(0.7), elevations(0.0));
+
+        // the coarsest octave keeps its orientation, the others are rotated
+        let provider = flat_provider(ReferenceEnvironmentParameters {
+            octave_rotation: 0.7,
+            ..Default::default()
+        });
+        assert_eq!(provider.octave_point(0.3, 0.4, 0, 1.0), (0.3, 0.4));
+        let (x, y) = provider.octave_point(0.3, 0.4, 2, 4.0);
+        assert!((x.hypot(y) - 2.0).abs() < 1e-12);
+        assert_ne!((x, y), (1.2, 1.6));
+    }
+
+    #[test]
+    fn elevation_of_constant_noise_is_predictable() {
+        // (continent noise, land noise, elevation (m)) by the default parameters:
+        // shelf = (|c|^0.5 * sign(c) - 1.0) * 0.3, elevation = land + shelf, squared if positive,
+        // in [-5000.0, 5000.0] m
+        for (continent, land, expected) in [
+            (1.0, 0.6, 0.36 * 5000.0),
+            (0.25, 0.1, -0.05 * 5000.0),
+            (-1.0, 0.2, -0.4 * 5000.0),
+        ] {
+            let provider = provider_with_channels(
+                vec![
+                    (
+                        NOISE_PRIMITIVE_CONTINENT,
+                        FnNoise(Box::new(move |_, _| continent)),
+                    ),
+                    (NOISE_PRIMITIVE_LAND, FnNoise(Box::new(move |_, _| land))),
+                ],
+                ReferenceEnvironmentParameters::default(),
+            );
+            let factors = provider.get_factors(0.3, -0.2).unwrap();
+            let elevation = factors.primitive_elevation_factors;
+            assert!((elevation.land_base - land).abs() < 1e-12);
+            assert!(
+                (elevation.elevation.value - expected).abs() < 1e-9,
+                "{continent} {land}"
+            );
+            // the persistence noise is 0.0, the middle of its range
+            assert!((elevation.persistence.value - 0.5).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn dry_inland_has_a_larger_diurnal_range_than_the_coast() {
+        // the continent falls from the heart of the continent at x = 1.0 to the deep ocean
+        let provider = provider_with_channels(
+            vec![
+                (
+                    NOISE_PRIMITIVE_CONTINENT,
+                    FnNoise(Box::new(|x, _| x.clamp(-1.0, 1.0))),
+                ),
+                (NOISE_PRIMITIVE_LAND, FnNoise(Box::new(|_, _| 0.5))),
+            ],
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        );
+        let diurnal_range = |x: f64| {
+            let factors = provider.get_factors(x, 0.0).unwrap();
+            (
+                factors.primitive_elevation_factors.elevation.normalized,
+                factors.diurnal_range,
+            )
+        };
+        let (inland, coast, ocean) = (diurnal_range(1.0), diurnal_range(0.3), diurnal_range(-0.9));
+        assert!(inland.0 > 0.0 && coast.0 > 0.0 && ocean.0 < 0.0);
+        assert!(
+            inland.1 > coast.1 && coast.1 > ocean.1,
+            "{inland:?} {coast:?} {ocean:?}"
+        );
+        let params = &provider.params;
+        assert_eq!(ocean.1, params.diurnal_range_base);
+        assert_eq!(
+            inland.1,
+            params.diurnal_range_base + params.diurnal_range_modulation
+        );
+    }
+
+    #[test]
+    fn global_drift_sets_the_mean_ocean_current() {
+        let mean_current = |global_ocean_drift: Option<(f64, f64)>| {
+            let provider = flat_provider(ReferenceEnvironmentParameters {
+                global_ocean_drift,
+                ..Default::default()
+            });
+            let (sum, count) = REGION
+                .grid_points(0.1)
+                .filter_map(|(_, (x, y))| provider.current_vector(CurrentField::Ocean, x, y))
+                .fold(((0.0, 0.0), 0), |((sx, sy), count), (vx, vy)| {
+                    ((sx + vx, sy + vy), count + 1)
+                });
+            (sum.0 / count as f64, sum.1 / count as f64)
+        };
+        let (vx, vy) = mean_current(None);
+        assert!(vx.hypot(vy) < 1e-12);
+        let (vx, vy) = mean_current(Some((0.3, 0.4)));
+        assert!(vx.hypot(vy) > 0.1, "{vx} {vy}");
+        assert!((vy.atan2(vx) - 0.4_f64.atan2(0.3)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn crater_is_an_inland_basin_and_a_coastal_slope_is_not() {
+        let params = || ReferenceEnvironmentParameters {
+            valid_fn: ValidModel::Everywhere.into_fn(),
+            ..Default::default()
+        };
+        // a bowl above the sea level around the origin
+        let crater = land_provider(|x, y| 0.3 + x * x + y * y, params());
+        assert!(crater.is_inland_basin(0.05, 0.03, 200));
+
+        // flat land on a continent rising along x, so the slope leads down to the sea
+        let coast = provider_with_channels(
+            vec![
+                (
+                    NOISE_PRIMITIVE_CONTINENT,
+                    FnNoise(Box::new(|x, _| x.clamp(-1.0, 1.0))),
+                ),
+                (NOISE_PRIMITIVE_LAND, FnNoise(Box::new(|_, _| 0.5))),
+            ],
+            params(),
+        );
+        assert!(coast.elevation_at(-0.9, 0.03).unwrap().normalized < 0.0);
+        assert!(!coast.is_inland_basin(0.05, 0.03, 1000));
+        let path = coast.trace_downhill(0.05, 0.03, 1000, 1.0 / 256.0);
+        let &(x, y) = path.last().unwrap();
+        assert!(coast.elevation_at(x, y).unwrap().normalized <= 0.0);
+    }
+
+    #[test]
+    fn shelf_blend_smooths_the_coast() {
+        let max_second_difference = |shelf_blend_smoothness: f64| {
+            // the elevation crosses the sea level along x
+            let provider = provider_with_channels(
+                vec![
+                    (
+                        NOISE_PRIMITIVE_CONTINENT,
+                        FnNoise(Box::new(|x, _| (0.4 + 0.2 * x).clamp(0.0, 1.0))),
+                    ),
+                    (NOISE_PRIMITIVE_LAND, FnNoise(Box::new(|_, _| 0.1))),
+                ],
+                ReferenceEnvironmentParameters {
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    shelf_blend_smoothness,
+                    ..Default::default()
+                },
+            );
+            let elevations = (-100..=100)
+                .map(|i| {
+                    provider
+                        .elevation_at(i as f64 * 0.01, 0.0)
+                        .unwrap()
+                        .normalized
+                })
+                .collect::<Vec<_>>();
+            assert!(elevations[0] < 0.0 && elevations[200] > 0.0);
+            elevations
+                .windows(3)
+                .map(|e| (e[0] - 2.0 * e[1] + e[2]).abs())
+                .fold(0.0, f64::max)
+        };
+        let sharp = max_second_difference(0.0);
+        let blended = max_second_difference(0.05);
+        assert!(blended < sharp * 0.5, "{blended} {sharp}");
+    }
+
+    #[test]
+    fn query_radius_returns_the_valid_points_within_the_radius() {
+        let everywhere = flat_provider(ReferenceEnvironmentParameters {
+            valid_fn: ValidModel::Everywhere.into_fn(),
+            ..Default::default()
+        });
+        let (center, radius) = ((0.2, -0.1), 0.3);
+        let points = everywhere.query_radius(center, radius, 0.05);
+        // about the area of the circle in cells
+        let expected = std::f64::consts::PI * (radius / 0.05).powi(2);
+        assert!((points.len() as f64 - expected).abs() < expected * 0.2);
+        for ((x, y), _) in &points {
+            assert!((x - center.0).hypot(y - center.1) <= radius);
+        }
+
+        // the half plane x < 0.2 is invalid
+        let half = flat_provider(ReferenceEnvironmentParameters {
+            valid_fn: Box::new(|x, _| x >= 0.2),
+            ..Default::default()
+        });
+        let valid = half.query_radius(center, radius, 0.05);
+        assert!(!valid.is_empty() && valid.len() < points.len());
+        assert!(valid.iter().all(|((x, _), _)| *x >= 0.2));
+        let in_half = points.iter().filter(|((x, _), _)| *x >= 0.2).count();
+        assert_eq!(valid.len(), in_half);
+    }
+
+    #[test]
+    fn geostrophic_wind_is_capped_near_the_equator() {
+        let magnitude = |latitude: f64, circulation_model: CirculationModel| {
+            let provider = provider_with(
+                WaveNoise::default(),
+                ReferenceEnvironmentParameters {
+                    circulation_model,
+                    ..params_with_latitude(move |_, _| latitude)
+                },
+            );
+            provider
+                .get_factors(0.3, 0.2)
+                .unwrap()
+                .atmosphere_current_magnitude
+                .unwrap()
+        };
+        let min_coriolis = ReferenceEnvironmentParameters::default().geostrophic_min_coriolis;
+        // |v| = |grad(p)| / max(|f|, geostrophic_min_coriolis), the gradient being the same
+        for latitude in [0.0, 0.01, -0.05, 0.8] {
+            let heuristic = magnitude(latitude, CirculationModel::Heuristic);
+            let geostrophic = magnitude(latitude, CirculationModel::Geostrophic);
+            assert!(heuristic != 0.0 && geostrophic.is_finite());
+            let expected = latitude.sin().abs().max(min_coriolis);
+            assert!(
+                (heuristic / geostrophic - expected).abs() < 1e-9,
+                "{latitude} {heuristic} {geostrophic}"
+            );
+        }
+    }
+
+    #[test]
+    fn noise_origin_shifts_the_pattern() {
+        let provider = |noise_origin: (f64, f64)| {
+            provider_with(
+                WaveNoise::default(),
+                ReferenceEnvironmentParameters {
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    noise_origin,
+                    ..Default::default()
+                },
+            )
+        };
+        let origin = provider((0.0, 0.0));
+        let shifted = provider((0.7, -0.4));
+        for (_, (x, y)) in REGION.grid_points(0.25) {
+            let expected = origin.elevation_at(x + 0.7, y - 0.4).unwrap().value;
+            let elevation = shifted.elevation_at(x, y).unwrap().value;
+            assert!(
+                (elevation - expected).abs() < 1e-9,
+                "{elevation} {expected}"
+            );
+        }
+        assert_ne!(
+            shifted.elevation_at(0.1, 0.1).unwrap().value,
+            origin.elevation_at(0.1, 0.1).unwrap().value
+        );
+    }
+
+    #[test]
+    fn arc_provider_is_sampled_from_several_threads() {
+        let provider = std::sync::Arc::new(provider_with(
+            WaveNoise::default(),
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        ));
+        let expected = REGION
+            .grid_points(0.25)
+            .map(|(_, (x, y))| provider.get_factors(x, y).unwrap().temperature_surface)
+            .collect::<Vec<_>>();
+        let handles = (0..4)
+            .map(|_| {
+                let provider = std::sync::Arc::clone(&provider);
+                std::thread::spawn(move || {
+                    REGION
+                        .grid_points(0.25)
+                        .map(|(_, (x, y))| provider.get_factors(x, y).unwrap().temperature_surface)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn temperature_inputs_reproduce_the_same_profile() {
+        let temperatures = |temperature_input: TemperatureInput,
+                            temperature_surface_fn: fn(f64) -> f64| {
+            let provider = flat_provider(ReferenceEnvironmentParameters {
+                temperature_input,
+                temperature_surface_fn: Box::new(temperature_surface_fn),
+                ..params_with_latitude(|_, y| y * 2.5)
+            });
+            REGION
+                .grid_points(0.1)
+                .map(|(_, (x, y))| provider.get_factors(x, y).unwrap().temperature_surface)
+                .collect::<Vec<_>>()
+        };
+        let by_latitude = temperatures(TemperatureInput::Latitude, |latitude| {
+            40.0 * latitude.cos() - 15.0
+        });
+        let by_insolation = temperatures(TemperatureInput::Insolation, |insolation| {
+            40.0 * insolation - 15.0
+        });
+        assert!(by_latitude[0] < by_latitude[by_latitude.len() / 2]);
+        for (a, b) in by_latitude.iter().zip(&by_insolation) {
+            assert!((a - b).abs() < 1e-9, "{a} {b}");
+        }
+    }
+
+    #[test]
+    fn ocean_current_detail_adds_finer_direction_changes() {
+        // total turning of the current along a line
+        let turning = |ocean_current_detail_weight: f64| {
+            let provider = provider_with(
+                WaveNoise::default(),
+                ReferenceEnvironmentParameters {
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    ocean_current_detail_weight,
+                    ..Default::default()
+                },
+            );
+            let angles = (-100..=100)
+                .map(|i| {
+                    let factors = provider.get_factors(i as f64 * 0.01, 0.1).unwrap();
+                    factors.ocean_current_angle.unwrap()
+                })
+                .collect::<Vec<_>>();
+            angles
+                .windows(2)
+                .map(|a| angle_difference(a[0], a[1]).abs())
+                .sum::<f64>()
+        };
+        let single = turning(0.0);
+        let detailed = turning(1.0);
+        assert!(detailed > single * 2.0, "{detailed} {single}");
+    }
+
+    #[test]
+    fn default_elevation_and_pressure_are_uncorrelated() {
+        // the pressure is the noise alone, without its latitudinal base
+        let provider = ReferenceEnvironmentProvider::new(
+            None,
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                atmosphere_pressure_noise_prop: 1.0,
+                ..Default::default()
+            },
+        );
+        let region = Region {
+            min_x: -20.0,
+            min_y: -20.0,
+            max_x: 20.0,
+            max_y: 20.0,
+        };
+        let (elevations, pressures): (Vec<_>, Vec<_>) = region
+            .grid_points(0.5)
+            .map(|(_, (x, y))| {
+                let factors = provider.get_factors(x, y).unwrap();
+                (
+                    factors.primitive_elevation_factors.elevation.normalized,
+                    factors.sea_level_pressure_normalized.unwrap(),
+                )
+            })
+            .unzip();
+        let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+        let (me, mp) = (mean(&elevations), mean(&pressures));
+        let (mut cov, mut ve, mut vp) = (0.0, 0.0, 0.0);
+        for (e, p) in elevations.iter().zip(&pressures) {
+            cov += (e - me) * (p - mp);
+            ve += (e - me).powi(2);
+            vp += (p - mp).powi(2);
+        }
+        let correlation = cov / (ve * vp).sqrt();
+        assert!(correlation.abs() < 0.2, "{correlation}");
+    }
+
+    #[test]
+    fn aggressive_persistence_keeps_the_land_bounded() {
+        let land_bases = |primitive_persistence_range: ValueRange| {
+            let provider = provider_with(
+                WaveNoise::default(),
+                ReferenceEnvironmentParameters {
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    primitive_persistence_range,
+                    ..Default::default()
+                },
+            );
+            REGION
+                .grid_points(0.1)
+                .map(|(_, (x, y))| {
+                    let factors = provider.get_primitive_elevation_factors(x, y);
+                    factors.land_base
+                })
+                .collect::<Vec<_>>()
+        };
+        let aggressive = land_bases(ValueRange { min: 1.5, max: 4.0 });
+        assert!(aggressive
+            .iter()
+            .all(|b| b.is_finite() && (0.0..=1.0).contains(b)));
+        // every persistence above the bound is clamped to it
+        let bound = land_bases(ValueRange {
+            min: MAX_STABLE_PERSISTENCE,
+            max: MAX_STABLE_PERSISTENCE,
+        });
+        assert_eq!(aggressive, bound);
+        assert_eq!(persistence_from_roughness(1.0), MAX_STABLE_PERSISTENCE);
+        assert_eq!(persistence_from_roughness(2.0), MAX_STABLE_PERSISTENCE);
+    }
+
+    #[test]
+    fn random_samples_are_valid_and_reproducible() {
+        // only the half x >= 0.0 is valid
+        let provider = flat_provider(ReferenceEnvironmentParameters {
+            valid_fn: Box::new(|x, _| x >= 0.0),
+            ..Default::default()
+        });
+        let points = |rng_seed: u64| {
+            provider
+                .sample_random(REGION, 200, rng_seed)
+                .into_iter()
+                .map(|(point, _)| point)
+                .collect::<Vec<_>>()
+        };
+        let samples = points(7);
+        assert_eq!(samples.len(), 200);
+        for &(x, y) in &samples {
+            assert!((0.0..=REGION.max_x).contains(&x));
+            assert!((REGION.min_y..=REGION.max_y).contains(&y));
+        }
+        assert_eq!(points(7), samples);
+        assert_ne!(points(8), samples);
+    }
+
+    #[test]
+    fn sampled_elevations_are_within_the_normalized_bounds() {
+        let variants: Vec<fn() -> ReferenceEnvironmentParameters> = vec![
+            ReferenceEnvironmentParameters::default,
+            || ReferenceEnvironmentParameters {
+                primitive_land_power: 0.5,
+                shelf_blend_smoothness: 0.2,
+                ..Default::default()
+            },
+            || ReferenceEnvironmentParameters {
+                primitive_abyssal_depth: Some(0.4),
+                land_elevation_scale: 1.5,
+                ocean_depth_scale: 0.7,
+                elevation_bias: 800.0,
+                ..Default::default()
+            },
+        ];
+        for params in variants {
+            let providers = [
+                ReferenceEnvironmentProvider::new(None, params()),
+                ReferenceEnvironmentProvider::new(Some([3; NOISE_END]), params()),
+            ];
+            for provider in providers {
+                let (min, max) = provider.elevation_normalized_bounds();
+                assert!(min < max);
+                for (_, (x, y)) in REGION.grid_points(0.02) {
+                    let elevation = provider.elevation_at(x, y).unwrap().normalized;
+                    // up to the rounding of the bias and the scales
+                    assert!(
+                        (min - 1e-12..=max + 1e-12).contains(&elevation),
+                        "{elevation} {min} {max}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn typed_position_gives_the_same_factors() {
+        let provider = provider_with(
+            WaveNoise::default(),
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        );
+        for (_, (x, y)) in REGION.grid_points(0.25) {
+            let factors = provider.get_factors(x, y).unwrap();
+            for pos in [WorldPos::new(x, y), WorldPos::from((x, y))] {
+                let typed = provider.get_factors_pos(pos).unwrap();
+                assert_eq!(format!("{typed:?}"), format!("{factors:?}"));
+            }
+        }
+        assert!(flat_provider(Default::default())
+            .get_factors_pos(WorldPos::new(0.0, 2.0))
+            .is_none());
+    }
+
+    #[test]
+    fn continent_noise_is_the_input_of_the_shelf() {
+        for fjord_strength in [0.0, 1.0] {
+            let params = ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                fjord_strength,
+                ..Default::default()
+            };
+            let (power, depth) = (params.primitive_shelf_power, params.primitive_shelf_depth);
+            let provider = ReferenceEnvironmentProvider::new(None, params);
+            for (_, (x, y)) in REGION.grid_points(0.1) {
+                let n = provider.continent_noise_at(x, y);
+                assert!((-1.0..=1.0).contains(&n));
+                let shelf = provider.get_primitive_elevation_factors(x, y).shelf;
+                assert_eq!(shelf, (n.abs().powf(power) * n.signum() - 1.0) * depth);
+            }
+        }
+    }
+
+    #[test]
+    fn point_factors_keep_the_order_of_the_points() {
+        // only |y| < 1.0 is valid (the band)
+        let provider = flat_provider(ReferenceEnvironmentParameters {
+            valid_fn: ValidModel::Band.into_fn(),
+            ..params_with_latitude(|x, _| x)
+        });
+        let points: [(f64, f64); 5] =
+            [(0.1, 0.0), (0.2, 3.0), (0.3, 0.5), (0.4, -2.0), (0.5, -0.5)];
+        let check = |factors: Vec<Option<EnvironmentFactors>>| {
+            assert_eq!(factors.len(), points.len());
+            for (factors, &(x, y)) in factors.iter().zip(&points) {
+                match factors {
+                    // the latitude tells which point the factors belong to
+                    Some(factors) => assert_eq!(factors.virtual_latitude, x),
+                    None => assert!(y.abs() >= 1.0),
+                }
+            }
+            assert_eq!(factors.iter().filter(|f| f.is_none()).count(), 2);
+        };
+        check(provider.get_factors_points(&points));
+        #[cfg(feature = "rayon")]
+        check(provider.get_factors_points_par(&points));
+    }
+
+    #[test]
+    fn poleward_current_warms_the_high_latitude() {
+        let temperature = |global_ocean_drift: Option<(f64, f64)>, steps: u32| {
+            // flat ocean, the north pole at y = 1.0
+            let provider = flat_provider(ReferenceEnvironmentParameters {
+                global_ocean_drift,
+                ocean_heat_transport_steps: steps,
+                ..params_with_latitude(|_, y| y * std::f64::consts::FRAC_PI_2)
+            });
+            provider.get_factors(0.0, 0.7).unwrap().temperature_surface
+        };
+        let baseline = temperature(None, 4);
+        let poleward = temperature(Some((0.0, 0.5)), 4);
+        assert!(poleward > baseline, "{poleward} {baseline}");
+    }
+
+    #[test]
+    fn domain_rotation_turns_the_whole_world() {
+        let provider = |domain_rotation: f64| {
+            provider_with(
+                WaveNoise::default(),
+                ReferenceEnvironmentParameters {
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    domain_rotation,
+                    ..Default::default()
+                },
+            )
+        };
+        let default = provider(ReferenceEnvironmentParameters::default().domain_rotation);
+        let unrotated = provider(0.0);
+        let rotated = provider(std::f64::consts::FRAC_PI_2);
+        for (_, (x, y)) in REGION.grid_points(0.25) {
+            let elevation = unrotated.elevation_at(x, y).unwrap().normalized;
+            assert_eq!(default.elevation_at(x, y).unwrap().normalized, elevation);
+            // the features along x now lie along y
+            // (up to the rounding of the rotation, raised by the shelf power near the coast)
+            let turned = rotated.elevation_at(y, -x).unwrap().normalized;
+            assert!((turned - elevation).abs() < 1e-6, "{turned} {elevation}");
+        }
+    }
+
+    #[test]
+    fn estimate_cost_drops_with_the_disabled_work() {
+        let estimate = |params: ReferenceEnvironmentParameters| {
+            flat_provider(params).estimate_cost(REGION, 0.1)
+        };
+        let default = estimate(Default::default());
+        let points = default.points;
+        assert_eq!(points, 21 * 11);
+        assert_eq!(default.gradient_passes, points);
+
+        // the sea level pressure and its gradient (5 samples per point)
+        let without_atmosphere = estimate(ReferenceEnvironmentParameters {
+            compute_atmosphere: false,
+            ..Default::default()
+        });
+        assert_eq!(without_atmosphere.gradient_passes, 0);
+        assert_eq!(
+            default.noise_samples - without_atmosphere.noise_samples,
+            5 * points
+        );
+
+        // one sample per point for each octave of the land
+        let fewer_octaves = estimate(ReferenceEnvironmentParameters {
+            primitive_land_octave_amplitudes: Some(vec![1.0, 0.5, 0.25]),
+            ..Default::default()
+        });
+        assert_eq!(
+            default.noise_samples - fewer_octaves.noise_samples,
+            (PRIMITIVE_LAND_OCTAVES as u64 - 3) * points
+        );
+    }
+
+    #[test]
+    fn temperature_clamp_bounds_any_closure() {
+        let clamp = ValueRange {
+            min: -20.0,
+            max: 35.0,
+        };
+        let provider = provider_with(
+            WaveNoise::default(),
+            ReferenceEnvironmentParameters {
+                // far beyond the clamp on both sides
+                temperature_surface_fn: Box::new(|latitude| latitude * 500.0),
+                temperature_clamp: Some(clamp),
+                ..params_with_latitude(|x, y| x + y)
+            },
+        );
+        let temperatures = REGION
+            .grid_points(0.1)
+            .map(|(_, (x, y))| provider.get_factors(x, y).unwrap().temperature_surface)
+            .collect::<Vec<_>>();
+        assert!(temperatures
+            .iter()
+            .all(|t| (clamp.min..=clamp.max).contains(t)));
+        assert!(temperatures.contains(&clamp.min) && temperatures.contains(&clamp.max));
+    }
+
+    #[test]
+    fn coastline_of_an_island_is_a_closed_circle() {
+        // the continent decreases radially from the origin
+        let provider = provider_with_channels(
+            vec![
+                (
+                    NOISE_PRIMITIVE_CONTINENT,
+                    FnNoise(Box::new(|x, y| (1.0 - (x * x + y * y) / 0.16).max(-1.0))),
+                ),
+                (NOISE_PRIMITIVE_LAND, FnNoise(Box::new(|_, _| 0.2))),
+            ],
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        );
+        let coastline = provider.coastline(REGION, 0.02);
+        assert_eq!(coastline.len(), 1);
+        let line = &coastline[0];
+        assert!(line.len() > 20);
+        assert_eq!(line.first(), line.last());
+        let radii = line.iter().map(|(x, y)| x.hypot(*y)).collect::<Vec<_>>();
+        let mean = radii.iter().sum::<f64>() / radii.len() as f64;
+        assert!(mean > 0.05);
+        for radius in radii {
+            assert!((radius - mean).abs() < 0.01, "{radius} {mean}");
+        }
+    }
+
+    #[test]
+    fn references_and_boxes_are_providers() {
+        fn temperature(provider: impl EnvironmentProvider, x: f64, y: f64) -> f64 {
+            provider.get_factors(x, y).unwrap().temperature_surface
+        }
+        let provider = provider_with(WaveNoise::default(), params_with_latitude(|_, y| y));
+        let boxed: Box<dyn EnvironmentProvider> = Box::new(provider_with(
+            WaveNoise::default(),
+            params_with_latitude(|_, y| y),
+        ));
+        for (_, (x, y)) in REGION.grid_points(0.25) {
+            let expected = provider.get_factors(x, y).unwrap().temperature_surface;
+            assert_eq!(temperature(&provider, x, y), expected);
+            assert_eq!(temperature(&boxed, x, y), expected);
+        }
+        assert_eq!(
+            temperature(boxed, 0.1, 0.2),
+            temperature(&provider, 0.1, 0.2)
+        );
+    }
+
+    #[test]
+    fn zonal_mean_temperature_falls_toward_the_poles() {
+        // the band |y| < 1.0 spans every latitude
+        let provider = ReferenceEnvironmentProvider::new(
+            None,
+            ReferenceEnvironmentParameters {
+                virtual_latitude_fn: LatitudeModel::Linear.into_fn(),
+                ..Default::default()
+            },
+        );
+        let region = Region {
+            min_x: -4.0,
+            min_y: -1.0,
+            max_x: 4.0,
+            max_y: 1.0,
+        };
+        let means = provider.zonal_means(region, 0.05, 6, |f| f.temperature_surface);
+        assert_eq!(means.len(), 6);
+        assert!(means.iter().all(|(_, mean)| mean.is_finite()));
+        // from the equator to the south pole, then to the north pole
+        for half in [&means[..3], &means[3..]] {
+            let mut half = half.to_vec();
+            half.sort_by(|a, b| a.0.abs().total_cmp(&b.0.abs()));
+            assert!(half[0].1 > half[1].1 && half[1].1 > half[2].1, "{half:?}");
+        }
+    }
+
+    #[test]
+    fn external_elevation_replaces_only_the_elevation() {
+        let provider = ExternalElevationProvider::new(
+            flat_provider(params_with_latitude(|_, y| y)),
+            // no data west of x = -0.8
+            |x, _| (x > -0.8).then_some(2000.0 * x),
+        );
+        for (_, (x, y)) in REGION.grid_points(0.1) {
+            let Some(factors) = provider.get_factors(x, y) else {
+                assert!(x <= -0.8);
+                continue;
+            };
+            let elevation = factors.primitive_elevation_factors.elevation;
+            assert!((elevation.value - 2000.0 * x).abs() < 1e-9);
+            assert_eq!(provider.elevation_at(x, y).unwrap().value, elevation.value);
+            assert_eq!(factors.virtual_latitude, y);
+        }
+        // colder poleward at the same elevation
+        let temperatures =
+            [0.0, 0.2, 0.4].map(|y| provider.get_factors(0.3, y).unwrap().temperature_surface);
+        assert!(temperatures[0] > temperatures[1] && temperatures[1] > temperatures[2]);
+    }
+
+    #[test]
+    fn zero_gradient_iteration_still_finds_the_slope() {
+        for slope_angle in [0.5_f64, 2.0, -2.5] {
+            let (cos, sin) = (slope_angle.cos(), slope_angle.sin());
+            let descent = |gradient_iteration: u32| {
+                let provider = flat_provider(ReferenceEnvironmentParameters {
+                    gradient_iteration,
+                    // rising along slope_angle
+                    ..params_with_latitude(move |x, y| x * cos + y * sin)
+                });
+                provider
+                    .field_gradient_at(
+                        0.1,
+                        0.2,
+                        0.01,
+                        |f| f.virtual_latitude,
+                        GradientDirection::Descent,
+                    )
+                    .unwrap()
+            };
+            let (angle, change) = descent(0);
+            assert!(change < -0.9, "{change}");
+            // within a step of the search directions
+            let step = std::f64::consts::TAU
+                / ReferenceEnvironmentParameters::default().gradient_sample_num as f64;
+            let expected = slope_angle + std::f64::consts::PI;
+            assert!(angle_difference(angle, expected).abs() < step, "{angle}");
+            assert_eq!((angle, change), descent(1));
+        }
+    }
+
+    #[test]
+    fn streamlines_follow_a_uniform_flow() {
+        let angle = 0.6_f64;
+        let flow = |global_ocean_drift: Option<(f64, f64)>| {
+            // flat ocean at the equator, so the drift is the whole current
+            flat_provider(ReferenceEnvironmentParameters {
+                global_ocean_drift,
+                ..params_with_latitude(|_, _| 0.0)
+            })
+        };
+        let provider = flow(Some((0.5 * angle.cos(), 0.5 * angle.sin())));
+        let lines = provider.streamlines(CurrentField::Ocean, REGION, 0.25, 0.5);
+        assert!(!lines.is_empty());
+        for line in &lines {
+            let len = line
+                .windows(2)
+                .map(|s| (s[1].0 - s[0].0).hypot(s[1].1 - s[0].1))
+                .sum::<f64>();
+            assert!(len <= 0.5 + 1e-9);
+            for segment in line.windows(2) {
+                let (dx, dy) = (segment[1].0 - segment[0].0, segment[1].1 - segment[0].1);
+                assert!(angle_difference(dy.atan2(dx), angle).abs() < 1e-6);
+            }
+        }
+        // no flow, no streamlines
+        let still = flow(None);
+        assert!(still
+            .streamlines(CurrentField::Ocean, REGION, 0.25, 0.5)
+            .is_empty());
+    }
+
+    #[test]
+    fn temperature_floor_is_approached_from_above() {
+        let temperatures = |temperature_floor: Option<f64>| {
+            let provider = flat_provider(ReferenceEnvironmentParameters {
+                // diverging toward the poles
+                temperature_surface_fn: Box::new(|latitude| 30.0 - 400.0 * latitude.powi(2)),
+                temperature_floor,
+                ..params_with_latitude(|_, y| y * 3.0)
+            });
+            REGION
+                .grid_points(0.05)
+                .map(|(_, (x, y))| provider.get_factors(x, y).unwrap().temperature_surface)
+                .collect::<Vec<_>>()
+        };
+        let raw = temperatures(None);
+        let floored = temperatures(Some(-40.0));
+        let min = floored.iter().copied().fold(f64::INFINITY, f64::min);
+        assert!(raw.iter().any(|&t| t < -500.0));
+        assert!((-40.0..-39.9).contains(&min), "{min}");
+        // smooth, not a clamp: every temperature is raised, less so far above the floor
+        for (raw, floored) in raw.iter().zip(&floored) {
+            assert!(floored > raw);
+            if *raw > 20.0 {
+                assert!(floored - raw < 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn boundary_falloff_lowers_only_the_margin() {
+        let elevation = |boundary_land_falloff: f64, x: f64| {
+            // land everywhere within |x| < 1.0
+            let provider = land_provider(
+                |_, _| 0.5,
+                ReferenceEnvironmentParameters {
+                    valid_fn: Box::new(|x, _| x.abs() < 1.0),
+                    boundary_land_falloff,
+                    ..Default::default()
+                },
+            );
+            provider.elevation_at(x, 0.0).unwrap().normalized
+        };
+        for x in [-0.95, 0.9, 0.97] {
+            assert!(elevation(0.2, x) < elevation(0.0, x));
+        }
+        // slopes down toward the edge
+        assert!(elevation(0.2, 0.97) < elevation(0.2, 0.9));
+        for x in [-0.5, 0.0, 0.6] {
+            assert_eq!(elevation(0.2, x), elevation(0.0, x));
+        }
+    }
+
+    #[test]
+    fn temperature_gradient_points_across_a_front() {
+        let angle = 2.2_f64;
+        let (cos, sin) = (angle.cos(), angle.sin());
+        // a sharp warming across the line through the origin perpendicular to `angle`
+        let provider = flat_provider(ReferenceEnvironmentParameters {
+            temperature_surface_fn: Box::new(|distance| 20.0 * (distance / 0.02).tanh()),
+            ..params_with_latitude(move |x, y| x * cos + y * sin)
+        });
+        let (front_angle, front) = provider.temperature_gradient_at(0.0, 0.0).unwrap();
+        assert!(angle_difference(front_angle, angle).abs() < 1e-3);
+        assert!((front - 1000.0).abs() < 10.0, "{front}");
+        let (_, away) = provider
+            .temperature_gradient_at(0.3 * cos, 0.3 * sin)
+            .unwrap();
+        assert!(away < front * 1e-3, "{away}");
+    }
+
+    #[test]
+    fn larger_ocean_floor_scale_smooths_the_ocean_floor() {
+        // mean squared change of the land base between neighbors along x, in deep ocean
+        let roughness = |ocean_floor_scale: Option<f64>| {
+            let provider = provider_with_channels(
+                vec![
+                    (NOISE_PRIMITIVE_CONTINENT, FnNoise(Box::new(|_, _| -1.0))),
+                    (
+                        NOISE_PRIMITIVE_LAND,
+                        FnNoise(Box::new(|x, y| (x * 5.0).sin() * (y * 4.0).cos())),
+                    ),
+                ],
+                ReferenceEnvironmentParameters {
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    ocean_floor_scale,
+                    ..Default::default()
+                },
+            );
+            let bases = (0..400)
+                .map(|i| {
+                    let x = -1.0 + i as f64 * 0.005;
+                    provider.get_primitive_elevation_factors(x, 0.1).land_base
+                })
+                .collect::<Vec<_>>();
+            bases.windows(2).map(|b| (b[1] - b[0]).powi(2)).sum::<f64>() / 399.0
+        };
+        let land_scale = roughness(None);
+        assert_eq!(roughness(Some(1.0)), land_scale);
+        assert!(roughness(Some(4.0)) < land_scale * 0.25);
+    }
+
+    #[test]
+    fn direction_only_currents_keep_the_direction() {
+        let currents = |ocean_current_output: CurrentOutput, current_magnitude_clamp| {
+            let provider = provider_with(
+                WaveNoise::default(),
+                ReferenceEnvironmentParameters {
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    ocean_current_output,
+                    current_magnitude_clamp,
+                    ..Default::default()
+                },
+            );
+            REGION
+                .grid_points(0.1)
+                .map(|(_, (x, y))| {
+                    let factors = provider.get_factors(x, y).unwrap();
+                    factors.get_vector(FactorLayer::OceanCurrent).unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+        let full = currents(CurrentOutput::Full, None);
+        let direction_only = currents(CurrentOutput::DirectionOnly, None);
+        for (&(angle, magnitude), &(unit_angle, unit)) in full.iter().zip(&direction_only) {
+            assert_eq!(unit, 1.0);
+            // the same direction as magnitude * (cos, sin) of the angle
+            let direction = (magnitude * angle.sin()).atan2(magnitude * angle.cos());
+            assert!(angle_difference(direction, unit_angle).abs() < 1e-9);
+        }
+
+        let bound = full.iter().map(|(_, m)| m.abs()).fold(0.0, f64::max) * 0.5;
+        let clamped = currents(CurrentOutput::Full, Some(bound));
+        for (&(angle, magnitude), &(clamped_angle, clamped)) in full.iter().zip(&clamped) {
+            assert_eq!(clamped_angle, angle);
+            assert_eq!(clamped, magnitude.clamp(-bound, bound));
+        }
+    }
+
+    #[test]
+    fn painted_mask_places_the_land() {
+        // land painted east of x = 0.3
+        let (cols, rows) = REGION.grid_size(0.1);
+        let mask = REGION
+            .grid_points(0.1)
+            .map(|(_, (x, _))| if x > 0.3 { 1.0 } else { 0.0 })
+            .collect::<Vec<_>>();
+        let provider = ContinentMaskProvider::from_grid(
+            provider_with(
+                WaveNoise {
+                    frequency: 6.0,
+                    amplitude: 0.5,
+                },
+                ReferenceEnvironmentParameters {
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    ..Default::default()
+                },
+            ),
+            mask,
+            cols,
+            rows,
+            REGION,
+        );
+        let mut land = Vec::new();
+        for (_, (x, y)) in REGION.grid_points(0.05) {
+            let elevation = provider.elevation_at(x, y).unwrap().normalized;
+            if x > 0.45 {
+                land.push(elevation);
+                assert!(elevation >= 0.0);
+            } else if x < 0.15 {
+                assert!(elevation < 0.0);
+            }
+        }
+        // the procedural relief stays on the painted land
+        let (min, max) = land
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &e| {
+                (min.min(e), max.max(e))
+            });
+        assert!(max - min > 0.05, "{min} {max}");
+        assert!(provider.get_factors(1.5, 0.0).is_none());
+    }
+
+    #[test]
+    fn temperature_noise_perturbs_the_closure_slightly() {
+        let temperatures = |temperature_noise_prop: f64| {
+            let provider = provider_with_channels(
+                vec![(
+                    NOISE_TEMPERATURE,
+                    FnNoise(Box::new(|x, y| (x * 3.0).sin() * (y * 2.0).cos())),
+                )],
+                ReferenceEnvironmentParameters {
+                    temperature_surface_fn: Box::new(|latitude| 25.0 - 30.0 * latitude.abs()),
+                    temperature_noise_prop,
+                    ..params_with_latitude(|_, y| y)
+                },
+            );
+            (0..20)
+                .map(|i| {
+                    let x = -1.0 + i as f64 * 0.1;
+                    provider.get_factors(x, 0.3).unwrap().temperature_surface
+                })
+                .collect::<Vec<_>>()
+        };
+        // exactly the closure at the latitude 0.3
+        assert!(temperatures(0.0).iter().all(|&t| t == 25.0 - 30.0 * 0.3));
+
+        let params = ReferenceEnvironmentParameters::default();
+        let band = 0.5 * params.temperature_noise_band;
+        let perturbed = temperatures(0.5);
+        assert!(perturbed.windows(2).any(|t| t[0] != t[1]));
+        for t in perturbed {
+            assert!((t - (25.0 - 30.0 * 0.3)).abs() <= band);
+        }
+    }
+
+    #[test]
+    fn higher_lod_is_smoother_and_samples_less_noise() {
+        let provider = land_provider(
+            |x, y| (x * 1.7).sin() * (y * 1.3 + 0.4).cos(),
+            params_with_latitude(|_, y| y),
+        );
+        // total variation of the elevation along a line
+        let variation = |lod: u32| {
+            let elevations = (0..400)
+                .map(|i| {
+                    let x = -1.0 + i as f64 * 0.005;
+                    provider
+                        .get_factors_lod(x, 0.2, lod)
+                        .unwrap()
+                        .primitive_elevation_factors
+                        .elevation
+                        .value
+                })
+                .collect::<Vec<_>>();
+            elevations
+                .windows(2)
+                .map(|e| (e[1] - e[0]).abs())
+                .sum::<f64>()
+        };
+        assert!(variation(2) < variation(0));
+        assert!(variation(4) < variation(2));
+
+        let noise = CountingNoise::default();
+        let provider = provider_with(noise.clone(), params_with_latitude(|_, y| y));
+        let mut samples = Vec::new();
+        for lod in [0, 2, 4] {
+            provider.get_factors_lod(0.1, 0.2, lod).unwrap();
+            samples.push(noise.take_count());
+        }
+        assert!(samples[1] < samples[0]);
+        assert!(samples[2] < samples[1]);
+    }
+
+    #[test]
+    fn asymmetric_scales_make_the_ocean_deeper_than_the_land_is_high() {
+        // (mean land height, mean ocean depth) in meters with the default seed
+        let means = |land_elevation_scale: f64, ocean_depth_scale: f64| {
+            let provider = ReferenceEnvironmentProvider::new(
+                None,
+                ReferenceEnvironmentParameters {
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    land_elevation_scale,
+                    ocean_depth_scale,
+                    ..Default::default()
+                },
+            );
+            let region = Region {
+                min_x: -10.0,
+                min_y: -10.0,
+                max_x: 10.0,
+                max_y: 10.0,
+            };
+            let elevations = region
+                .grid_points(0.25)
+                .map(|(_, (x, y))| provider.elevation_at(x, y).unwrap().value)
+                .collect::<Vec<_>>();
+            let mean = |values: Vec<f64>| values.iter().sum::<f64>() / values.len() as f64;
+            let land = elevations.iter().filter(|&&e| e > 0.0).copied().collect();
+            let ocean = elevations
+                .iter()
+                .filter(|&&e| e < 0.0)
+                .map(|e| -e)
+                .collect();
+            (mean(land), mean(ocean))
+        };
+        let (land, ocean) = means(1.0, 1.0);
+        let (scaled_land, scaled_ocean) = means(0.5, 2.0);
+        assert!(scaled_ocean > scaled_land);
+        assert!(scaled_ocean / scaled_land > ocean / land);
+    }
+
+    #[test]
+    fn finer_resolution_measures_a_longer_coastline() {
+        let provider = ReferenceEnvironmentProvider::new(
+            None,
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        );
+        let region = Region {
+            min_x: -5.0,
+            min_y: -5.0,
+            max_x: 5.0,
+            max_y: 5.0,
+        };
+        let lengths = [0.4, 0.2, 0.1, 0.05]
+            .map(|resolution| provider.coastline_length(region, resolution, 0.0));
+        assert!(lengths[0] > 0.0);
+        assert!(lengths.windows(2).all(|l| l[0] <= l[1]), "{lengths:?}");
+    }
+
+    #[test]
+    fn pressure_stays_within_the_documented_range() {
+        for blend in [PressureNoiseBlend::Signed, PressureNoiseBlend::Unsigned] {
+            for prop in [0.0, 0.3, 0.7, 1.0] {
+                let params = ReferenceEnvironmentParameters {
+                    atmosphere_pressure_noise_prop: prop,
+                    atmosphere_pressure_noise_blend: blend,
+                    valid_fn: ValidModel::Everywhere.into_fn(),
+                    ..params_with_latitude(|_, y| y)
+                };
+                // noise beyond [-1.0, 1.0] to reach the clamp
+                let noise = WaveNoise {
+                    amplitude: 2.0,
+                    ..Default::default()
+                };
+                let provider = provider_with(noise, params);
+                let lower = match blend {
+                    PressureNoiseBlend::Signed => -prop,
+                    PressureNoiseBlend::Unsigned => 0.0,
+                };
+                for (_, (x, y)) in REGION.grid_points(0.05) {
+                    let factors = provider.get_factors(x, y).unwrap();
+                    for pressure in [
+                        factors.sea_level_pressure_normalized.unwrap(),
+                        factors.atmosphere_pressure_normalized.unwrap(),
+                    ] {
+                        assert!(
+                            (lower..=1.0).contains(&pressure),
+                            "{blend:?} {prop}: {pressure}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hexgrid_samples_every_cell_center() {
+        let provider = provider_with(WaveNoise::default(), params_with_latitude(|_, y| y));
+        for orientation in [HexOrientation::PointyTop, HexOrientation::FlatTop] {
+            let samples = provider.get_factors_hexgrid(REGION, 0.2, orientation);
+            let cells = HexCoord::cells_in(REGION, 0.2, orientation);
+            assert_eq!(samples.len(), cells.len());
+            for ((cell, factors), expected) in samples.into_iter().zip(cells) {
+                assert_eq!(cell, expected);
+                let (x, y) = cell.center(0.2, orientation);
+                assert!(factors_approx_eq(
+                    &factors.unwrap(),
+                    &provider.get_factors(x, y).unwrap(),
+                    &FactorTolerance::default()
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn elevation_bias_shifts_every_elevation() {
+        let bias = 300.0;
+        let provider = |elevation_bias: f64| {
+            provider_with(
+                WaveNoise::default(),
+                ReferenceEnvironmentParameters {
+                    elevation_bias,
+                    ..params_with_latitude(|_, y| y)
+                },
+            )
+        };
+        let (unbiased, biased) = (provider(0.0), provider(bias));
+        let range = ReferenceEnvironmentParameters::default().primitive_elevation_range;
+        let mut raised = 0;
+        for (_, (x, y)) in REGION.grid_points(0.02) {
+            let before = unbiased.elevation_at(x, y).unwrap();
+            let after = biased.elevation_at(x, y).unwrap();
+            let expected = (before.value + bias).min(range.max);
+            assert!((after.value - expected).abs() < 1e-9);
+            let normalized = ValueWithNormalized::from_signed_value(after.value, range).normalized;
+            assert!((after.normalized - normalized).abs() < 1e-12);
+            if before.value < 0.0 && after.value >= 0.0 {
+                raised += 1;
+            }
+        }
+        // some of the ocean is raised above the sea level
+        assert!(raised > 0);
+    }
+
+    #[test]
+    fn nearest_peak_is_the_closer_of_two_peaks() {
+        let peaks = [(-0.4, 0.1), (0.5, 0.1)];
+        let bump = move |x: f64, y: f64, (px, py): (f64, f64)| {
+            (-((x - px).powi(2) + (y - py).powi(2)) / 0.02).exp()
+        };
+        let provider = land_provider(
+            move |x, y| 0.2 + 0.6 * (bump(x, y, peaks[0]) + bump(x, y, peaks[1])),
+            // a single octave in the world coordinates, without finer peaks
+            ReferenceEnvironmentParameters {
+                primitive_land_scale: 1.0,
+                primitive_land_octave_amplitudes: Some(vec![1.0]),
+                ..params_with_latitude(|_, y| y)
+            },
+        );
+        let resolution = 0.02;
+        for (query, nearest) in [((0.2, 0.0), peaks[1]), ((-0.1, 0.2), peaks[0])] {
+            let (peak, distance) = provider
+                .nearest_peak(query.0, query.1, 1.0, resolution)
+                .unwrap();
+            assert!((peak.0 - nearest.0).hypot(peak.1 - nearest.1) <= resolution);
+            assert_eq!(distance, (peak.0 - query.0).hypot(peak.1 - query.1));
+        }
+    }
+
+    #[test]
+    fn temperature_profile_reaches_its_equator_and_pole_temperatures() {
+        let profile = TemperatureProfile {
+            equator_temperature: 28.0,
+            pole_temperature: -22.0,
+        };
+        let provider = flat_provider(ReferenceEnvironmentParameters {
+            temperature_profile: Some(profile),
+            // bypassed by the profile
+            temperature_surface_fn: Box::new(|_| 100.0),
+            ..params_with_latitude(|_, y| y * std::f64::consts::PI)
+        });
+        let temperature = |x: f64, y: f64| provider.get_factors(x, y).unwrap().temperature_surface;
+        for x in [-0.7, 0.0, 0.4] {
+            assert!((temperature(x, 0.0) - 28.0).abs() < 1e-9);
+            assert!((temperature(x, 0.5) + 22.0).abs() < 1e-9);
+            assert!((temperature(x, -0.5) + 22.0).abs() < 1e-9);
+            assert!((-22.0..=28.0).contains(&temperature(x, 0.2)));
+        }
+    }
+
+    #[test]
+    fn open_water_upwind_gives_higher_waves_than_a_sheltered_coast() {
+        // an island around the origin under a uniform wind
+        let provider = provider_with_channels(
+            vec![
+                (
+                    NOISE_PRIMITIVE_CONTINENT,
+                    FnNoise(Box::new(|x, y| {
+                        (0.3 - 4.0 * (x * x + y * y)).clamp(-1.0, 1.0)
+                    })),
+                ),
+                (NOISE_PRIMITIVE_LAND, FnNoise(Box::new(|_, _| 0.5))),
+                (
+                    NOISE_ATMOSPHERE_PRESSURE,
+                    FnNoise(Box::new(|x, y| 0.3 * x + 0.1 * y)),
+                ),
+            ],
+            ReferenceEnvironmentParameters {
+                atmosphere_pressure_noise_prop: 1.0,
+                ..params_with_latitude(|_, _| 0.5)
+            },
+        );
+        assert!(provider.elevation_at(0.0, 0.0).unwrap().normalized > 0.0);
+        let factors = provider.get_factors(0.0, 0.0).unwrap();
+        let (angle, magnitude) = factors.get_vector(FactorLayer::AtmosphereCurrent).unwrap();
+        assert!(magnitude != 0.0);
+        let (downwind_x, downwind_y) = (
+            angle.cos() * magnitude.signum(),
+            angle.sin() * magnitude.signum(),
+        );
+
+        // the first ocean point from the origin along `sign` * the wind
+        let offshore = |sign: f64| {
+            (1..)
+                .map(|k| k as f64 * 0.01 * sign)
+                .map(|d| (downwind_x * d, downwind_y * d))
+                .find(|&(x, y)| provider.elevation_at(x, y).unwrap().normalized <= 0.0)
+                .unwrap()
+        };
+        // the island is upwind of the lee shore, the open ocean upwind of the windward shore
+        let (lee_x, lee_y) = offshore(1.0);
+        let (windward_x, windward_y) = offshore(-1.0);
+        let sheltered = provider.wave_height_at(lee_x, lee_y).unwrap();
+        let exposed = provider.wave_height_at(windward_x, windward_y).unwrap();
+        assert!(exposed > sheltered, "{exposed} {sheltered}");
+
+        // no waves on land
+        assert_eq!(provider.wave_height_at(0.0, 0.0), None);
+    }
+
+    #[test]
+    fn cached_provider_is_shared_between_threads() {
+        let provider = provider_with(
+            WaveNoise::default(),
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        );
+        let expected = REGION
+            .grid_points(0.25)
+            .map(|(_, (x, y))| provider.get_factors(x, y).unwrap().temperature_surface)
+            .collect::<Vec<_>>();
+        // small enough that the threads evict each other's points
+        let cached = std::sync::Arc::new(CachedEnvironmentProvider::new(provider, 0.25, 8));
+        let handles = (0..4)
+            .map(|_| {
+                let cached = std::sync::Arc::clone(&cached);
+                std::thread::spawn(move || {
+                    REGION
+                        .grid_points(0.25)
+                        .map(|(_, (x, y))| cached.get_factors(x, y).unwrap().temperature_surface)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), expected);
+        }
+        let cache = cached.lock_cache();
+        assert!(cache.entries.len() <= 8);
+        assert_eq!(cache.entries.len(), cache.usage.len());
+    }
+
+    #[test]
+    fn land_ratio_of_no_valid_area_is_zero() {
+        let provider = flat_provider(ReferenceEnvironmentParameters {
+            valid_fn: Box::new(|_, _| false),
+            ..Default::default()
+        });
+        let area = provider.area_breakdown(REGION, 0.25, 0.0);
+        assert_eq!(area, AreaBreakdown::default());
+        assert_eq!(area.land_ratio(), 0.0);
+
+        let area = AreaBreakdown {
+            land_area: 1.0,
+            ocean_area: 3.0,
+        };
+        assert_eq!(area.land_ratio(), 0.25);
+    }
+
+    #[test]
+    fn heat_transport_traces_over_the_external_elevation() {
+        let temperature = |elevation_fn: fn(f64, f64) -> Option<f64>| {
+            // flat ocean drifting poleward, the north pole at y = 1.0
+            let provider = flat_provider(ReferenceEnvironmentParameters {
+                global_ocean_drift: Some((0.0, 0.5)),
+                ocean_heat_transport_steps: 4,
+                ..params_with_latitude(|_, y| y * std::f64::consts::FRAC_PI_2)
+            });
+            ExternalElevationProvider::new(provider, elevation_fn)
+                .get_factors(0.0, 0.7)
+                .unwrap()
+                .temperature_surface
+        };
+        let ocean = temperature(|_, _| Some(-1000.0));
+        // the first step upstream lands on a coast, where the current stops
+        let coast = temperature(|_, y| Some(if y < 0.69 { 5000.0 } else { -1000.0 }));
+        assert!(coast < ocean, "{coast} {ocean}");
+    }
+}
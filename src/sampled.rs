@@ -0,0 +1,632 @@
+//! Factors sampled on a grid for repeated lookups
+
+use std::collections::HashMap;
+
+use crate::*;
+
+/// Interpolation order of [SampledEnvironment::factors_at]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InterpOrder {
+    /// The nearest grid point
+    Nearest,
+    /// The 2x2 surrounding grid points
+    #[default]
+    Bilinear,
+    /// The 4x4 surrounding grid points (Catmull-Rom), for smooth isolines
+    Bicubic,
+}
+
+/// Factors sampled on the grid over `region` every `resolution`, for repeated lookups
+pub struct SampledEnvironment {
+    region: Region,
+    resolution: f64,
+    cols: usize,
+    rows: usize,
+    factors: Vec<Option<EnvironmentFactors>>,
+}
+
+impl SampledEnvironment {
+    pub fn new(provider: &impl EnvironmentProvider, region: Region, resolution: f64) -> Self {
+        Self::new_supersampled(provider, region, resolution, 1)
+    }
+
+    /// Samples every grid point as the average of a `supersample` x `supersample` subgrid
+    /// spanning the cell around it (anti-aliasing the coastline).
+    /// The factors are blended like [Self::factors_at], except that the elevation is
+    /// averaged over the majority of land or ocean subsamples so that the grid point
+    /// stays on the side of the sea level most of the cell is on.
+    /// Invalid subsamples are ignored (0 or 1: one sample per grid point).
+    pub fn new_supersampled(
+        provider: &impl EnvironmentProvider,
+        region: Region,
+        resolution: f64,
+        supersample: u32,
+    ) -> Self {
+        let (cols, rows) = region.grid_size(resolution);
+        let factors = region
+            .grid_points(resolution)
+            .map(|(_, (x, y))| {
+                if supersample <= 1 {
+                    return provider.get_factors(x, y);
+                }
+                let n = supersample as usize;
+                let offset = |k: usize| resolution * ((k as f64 + 0.5) / n as f64 - 0.5);
+                let subsamples = (0..n)
+                    .flat_map(|j| (0..n).map(move |i| (i, j)))
+                    .filter_map(|(i, j)| provider.get_factors(x + offset(i), y + offset(j)))
+                    .collect::<Vec<_>>();
+                supersample_factors(&subsamples)
+            })
+            .collect();
+        Self {
+            region,
+            resolution,
+            cols,
+            rows,
+            factors,
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn resolution(&self) -> f64 {
+        self.resolution
+    }
+
+    /// (columns, rows)
+    pub fn size(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    /// Factors at the grid point (ix, iy)
+    pub fn get(&self, ix: usize, iy: usize) -> Option<&EnvironmentFactors> {
+        if ix >= self.cols || iy >= self.rows {
+            return None;
+        }
+        self.factors[iy * self.cols + ix].as_ref()
+    }
+
+    /// Factors at (x, y) interpolated from the grid.
+    /// Falls back to the next lower order when any of the required grid points is invalid
+    /// (and returns None when the nearest grid point is invalid or (x, y) is outside the grid).
+    pub fn factors_at(&self, x: f64, y: f64, order: InterpOrder) -> Option<EnvironmentFactors> {
+        let gx = (x - self.region.min_x) / self.resolution;
+        let gy = (y - self.region.min_y) / self.resolution;
+        if !(gx >= 0.0 && gy >= 0.0 && gx <= (self.cols - 1) as f64 && gy <= (self.rows - 1) as f64)
+        {
+            return None;
+        }
+
+        // base grid point and offset within the cell
+        let ix = (gx.floor() as usize).min(self.cols.saturating_sub(2));
+        let iy = (gy.floor() as usize).min(self.rows.saturating_sub(2));
+        let (tx, ty) = (gx - ix as f64, gy - iy as f64);
+
+        if order == InterpOrder::Bicubic {
+            let weights_x = catmull_rom_weights(tx);
+            let weights_y = catmull_rom_weights(ty);
+            let samples = (0..4)
+                .flat_map(|j| (0..4).map(move |i| (i, j)))
+                .map(|(i, j)| {
+                    let (sx, sy) = ((ix + i).checked_sub(1)?, (iy + j).checked_sub(1)?);
+                    Some((weights_x[i] * weights_y[j], self.get(sx, sy)?))
+                })
+                .collect::<Option<Vec<_>>>();
+            if let Some(samples) = samples {
+                return Some(blend_factors(&samples));
+            }
+        }
+        if order != InterpOrder::Nearest {
+            let samples = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                .into_iter()
+                .map(|(i, j)| {
+                    let wx = if i == 0 { 1.0 - tx } else { tx };
+                    let wy = if j == 0 { 1.0 - ty } else { ty };
+                    Some((wx * wy, self.get(ix + i, iy + j)?))
+                })
+                .collect::<Option<Vec<_>>>();
+            if let Some(samples) = samples {
+                return Some(blend_factors(&samples));
+            }
+        }
+        self.get(gx.round() as usize, gy.round() as usize).copied()
+    }
+
+    /// Drainage basin label of each grid point (row-major, same layout as [Self::get]).
+    /// Every land point follows the steepest descent among its 8 neighbors until it reaches
+    /// the ocean (the ocean point it flows into is the outlet) or a sink (a land point without
+    /// lower valid neighbors); points draining to the same outlet or sink share a label.
+    /// Labels start from 1 in row-major order of their first point;
+    /// ocean and invalid points are labeled 0.
+    pub fn watershed_labels(&self) -> Vec<u32> {
+        let elevation = |i: usize| {
+            self.factors[i]
+                .as_ref()
+                .map(|factors| factors.primitive_elevation_factors.elevation)
+        };
+        let is_land = |i: usize| elevation(i).is_some_and(|e| e.normalized > 0.0);
+
+        let downstream = (0..self.factors.len())
+            .map(|i| {
+                let center = elevation(i)?.value;
+                let (ix, iy) = ((i % self.cols) as isize, (i / self.cols) as isize);
+                (-1..=1)
+                    .flat_map(|dy| (-1..=1).map(move |dx| (ix + dx, iy + dy)))
+                    .filter(|&(nx, ny)| {
+                        (nx, ny) != (ix, iy)
+                            && nx >= 0
+                            && ny >= 0
+                            && (nx as usize) < self.cols
+                            && (ny as usize) < self.rows
+                    })
+                    .map(|(nx, ny)| ny as usize * self.cols + nx as usize)
+                    .filter_map(|n| Some((n, elevation(n)?.value)))
+                    .filter(|&(_, value)| value < center)
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(n, _)| n)
+            })
+            .collect::<Vec<_>>();
+
+        // terminal (outlet or sink) of each land point, resolved along the descent path
+        let mut terminal: Vec<Option<usize>> = vec![None; self.factors.len()];
+        let mut path = Vec::new();
+        for start in (0..self.factors.len()).filter(|&i| is_land(i)) {
+            let mut current = start;
+            let end = loop {
+                if let Some(end) = terminal[current] {
+                    break end;
+                }
+                if !is_land(current) {
+                    break current;
+                }
+                path.push(current);
+                match downstream[current] {
+                    Some(next) => current = next,
+                    None => break current,
+                }
+            };
+            for i in path.drain(..) {
+                terminal[i] = Some(end);
+            }
+        }
+
+        let mut label_of_terminal = HashMap::new();
+        terminal
+            .into_iter()
+            .map(|end| match end {
+                Some(end) => {
+                    let next = label_of_terminal.len() as u32 + 1;
+                    *label_of_terminal.entry(end).or_insert(next)
+                }
+                None => 0,
+            })
+            .collect()
+    }
+
+    /// Signed distance (in the world coordinates) of each grid point to the coastline at
+    /// `sea_level` (a normalized elevation), positive on land and negative over the ocean
+    /// (row-major, same layout as [Self::get]).
+    /// The coastline is placed between adjacent grid points by linear interpolation of the
+    /// elevation, so the field passes through 0.0 continuously across the coast, and the
+    /// distance is propagated over the valid points by a two-pass chamfer transform
+    /// (within a few percent of the euclidean distance).
+    /// Invalid points are NaN, and points with no coastline among the valid points are
+    /// +-infinity.
+    pub fn ocean_distance(&self, sea_level: f64) -> Vec<f64> {
+        let height = |i: usize| {
+            self.factors[i]
+                .as_ref()
+                .map(|factors| factors.primitive_elevation_factors.elevation.normalized - sea_level)
+        };
+        let index = |ix: isize, iy: isize| {
+            (ix >= 0 && iy >= 0 && (ix as usize) < self.cols && (iy as usize) < self.rows)
+                .then(|| iy as usize * self.cols + ix as usize)
+        };
+
+        // seed the points next to the coastline with the distance to the crossing
+        let mut distance = vec![f64::INFINITY; self.factors.len()];
+        for i in 0..self.factors.len() {
+            let Some(h) = height(i) else {
+                continue;
+            };
+            let (ix, iy) = ((i % self.cols) as isize, (i / self.cols) as isize);
+            for n in [index(ix + 1, iy), index(ix, iy + 1)].into_iter().flatten() {
+                let Some(hn) = height(n) else {
+                    continue;
+                };
+                if (h >= 0.0) != (hn >= 0.0) {
+                    let t = h / (h - hn);
+                    distance[i] = distance[i].min(t * self.resolution);
+                    distance[n] = distance[n].min((1.0 - t) * self.resolution);
+                }
+            }
+        }
+
+        let diagonal = self.resolution * std::f64::consts::SQRT_2;
+        let forward = [
+            (-1, -1, diagonal),
+            (0, -1, self.resolution),
+            (1, -1, diagonal),
+            (-1, 0, self.resolution),
+        ];
+        let backward = forward.map(|(dx, dy, step)| (-dx, -dy, step));
+        let mut relax = |i: usize, offsets: &[(isize, isize, f64)]| {
+            if height(i).is_none() {
+                return;
+            }
+            let (ix, iy) = ((i % self.cols) as isize, (i / self.cols) as isize);
+            for &(dx, dy, step) in offsets {
+                if let Some(n) = index(ix + dx, iy + dy).filter(|&n| height(n).is_some()) {
+                    distance[i] = distance[i].min(distance[n] + step);
+                }
+            }
+        };
+        for i in 0..self.factors.len() {
+            relax(i, &forward);
+        }
+        for i in (0..self.factors.len()).rev() {
+            relax(i, &backward);
+        }
+
+        distance
+            .into_iter()
+            .enumerate()
+            .map(|(i, d)| match height(i) {
+                Some(h) if h >= 0.0 => d,
+                Some(_) => -d,
+                None => f64::NAN,
+            })
+            .collect()
+    }
+}
+
+/// [SampledEnvironment] over a scrolling window: moving the window reuses the samples
+/// of the overlap and only computes the newly exposed grid points
+pub struct ScrollBuffer {
+    sampled: SampledEnvironment,
+}
+
+impl ScrollBuffer {
+    pub fn new(provider: &impl EnvironmentProvider, region: Region, resolution: f64) -> Self {
+        Self {
+            sampled: SampledEnvironment::new(provider, region, resolution),
+        }
+    }
+
+    /// The samples of the current window
+    pub fn sampled(&self) -> &SampledEnvironment {
+        &self.sampled
+    }
+
+    /// Moves the window so that its minimum corner is at (`min_x`, `min_y`),
+    /// snapped to the grid of the current window (keeping the size).
+    /// Returns the number of grid points computed.
+    pub fn scroll_to(
+        &mut self,
+        provider: &impl EnvironmentProvider,
+        min_x: f64,
+        min_y: f64,
+    ) -> usize {
+        let sampled = &self.sampled;
+        let resolution = sampled.resolution;
+        let shift_x = ((min_x - sampled.region.min_x) / resolution).round() as isize;
+        let shift_y = ((min_y - sampled.region.min_y) / resolution).round() as isize;
+        let region = Region {
+            min_x: sampled.region.min_x + shift_x as f64 * resolution,
+            min_y: sampled.region.min_y + shift_y as f64 * resolution,
+            max_x: sampled.region.max_x + shift_x as f64 * resolution,
+            max_y: sampled.region.max_y + shift_y as f64 * resolution,
+        };
+        let (cols, rows) = (sampled.cols, sampled.rows);
+
+        let mut computed = 0;
+        // the window keeps its grid, only shifted
+        let factors = sampled
+            .region
+            .grid_points(resolution)
+            .map(|((ix, iy), _)| {
+                let (old_ix, old_iy) = (ix as isize + shift_x, iy as isize + shift_y);
+                if old_ix >= 0
+                    && old_iy >= 0
+                    && (old_ix as usize) < cols
+                    && (old_iy as usize) < rows
+                {
+                    return sampled.factors[old_iy as usize * cols + old_ix as usize];
+                }
+                computed += 1;
+                let (x, y) = region.grid_point(resolution, ix, iy);
+                provider.get_factors(x, y)
+            })
+            .collect();
+
+        self.sampled = SampledEnvironment {
+            region,
+            resolution,
+            cols,
+            rows,
+            factors,
+        };
+        computed
+    }
+}
+
+/// Average of the subsamples of a grid point, with the elevation averaged over
+/// the majority of land (or ocean) subsamples
+fn supersample_factors(subsamples: &[EnvironmentFactors]) -> Option<EnvironmentFactors> {
+    if subsamples.is_empty() {
+        return None;
+    }
+    let weight = 1.0 / subsamples.len() as f64;
+    let weighted = subsamples
+        .iter()
+        .map(|factors| (weight, factors))
+        .collect::<Vec<_>>();
+    let mut factors = blend_factors(&weighted);
+
+    let is_land = |factors: &&EnvironmentFactors| {
+        factors.primitive_elevation_factors.elevation.normalized > 0.0
+    };
+    let land_count = subsamples.iter().filter(is_land).count();
+    let majority_land = land_count * 2 > subsamples.len();
+    let majority = subsamples
+        .iter()
+        .filter(|factors| is_land(factors) == majority_land)
+        .collect::<Vec<_>>();
+    let weight = 1.0 / majority.len() as f64;
+    factors.primitive_elevation_factors.elevation = ValueWithNormalized {
+        value: majority
+            .iter()
+            .map(|f| weight * f.primitive_elevation_factors.elevation.value)
+            .sum(),
+        normalized: majority
+            .iter()
+            .map(|f| weight * f.primitive_elevation_factors.elevation.normalized)
+            .sum(),
+    };
+    Some(factors)
+}
+
+/// Catmull-Rom weights of the 4 points around t (in [0, 1] between the 2nd and the 3rd)
+fn catmull_rom_weights(t: f64) -> [f64; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        0.5 * (-t3 + 2.0 * t2 - t),
+        0.5 * (3.0 * t3 - 5.0 * t2 + 2.0),
+        0.5 * (-3.0 * t3 + 4.0 * t2 + t),
+        0.5 * (t3 - t2),
+    ]
+}
+
+/// Weighted sum of factors (weights summing to 1.0);
+/// angles are blended as unit vectors, and a factor not computed in every sample is None
+fn blend_factors(samples: &[(f64, &EnvironmentFactors)]) -> EnvironmentFactors {
+    let sum = |f: fn(&EnvironmentFactors) -> f64| -> f64 {
+        samples.iter().map(|(w, factors)| w * f(factors)).sum()
+    };
+    let sum_opt = |f: fn(&EnvironmentFactors) -> Option<f64>| -> Option<f64> {
+        samples
+            .iter()
+            .map(|(w, factors)| Some(w * f(factors)?))
+            .sum()
+    };
+    let angle = |f: fn(&EnvironmentFactors) -> Option<f64>| -> Option<f64> {
+        let x: Option<f64> = samples
+            .iter()
+            .map(|(w, factors)| Some(w * f(factors)?.cos()))
+            .sum();
+        let y: Option<f64> = samples
+            .iter()
+            .map(|(w, factors)| Some(w * f(factors)?.sin()))
+            .sum();
+        Some(y?.atan2(x?))
+    };
+    EnvironmentFactors {
+        virtual_latitude: sum(|f| f.virtual_latitude),
+        temperature_surface: sum(|f| f.temperature_surface),
+        diurnal_range: sum(|f| f.diurnal_range),
+
+        sea_level_pressure_normalized: sum_opt(|f| f.sea_level_pressure_normalized),
+        atmosphere_pressure_normalized: sum_opt(|f| f.atmosphere_pressure_normalized),
+        atmosphere_current_angle: angle(|f| f.atmosphere_current_angle),
+        atmosphere_current_magnitude: sum_opt(|f| f.atmosphere_current_magnitude),
+        atmosphere_current_speed: sum_opt(|f| f.atmosphere_current_speed),
+
+        primitive_elevation_factors: PrimitiveElevationFactors {
+            shelf: sum(|f| f.primitive_elevation_factors.shelf),
+            persistence: ValueWithNormalized {
+                value: sum(|f| f.primitive_elevation_factors.persistence.value),
+                normalized: sum(|f| f.primitive_elevation_factors.persistence.normalized),
+            },
+            land_base: sum(|f| f.primitive_elevation_factors.land_base),
+            elevation: ValueWithNormalized {
+                value: sum(|f| f.primitive_elevation_factors.elevation.value),
+                normalized: sum(|f| f.primitive_elevation_factors.elevation.normalized),
+            },
+        },
+        ocean_current_angle: angle(|f| f.ocean_current_angle),
+        ocean_current_magnitude: sum_opt(|f| f.ocean_current_magnitude),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    const REGION: Region = Region {
+        min_x: -1.0,
+        min_y: -0.5,
+        max_x: 1.0,
+        max_y: 0.5,
+    };
+
+    fn wave_provider() -> ReferenceEnvironmentProvider<WaveNoise> {
+        provider_with(
+            WaveNoise::default(),
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn elevation(factors: &EnvironmentFactors) -> f64 {
+        factors.primitive_elevation_factors.elevation.normalized
+    }
+
+    #[test]
+    fn nearest_interpolation_returns_the_grid_points() {
+        let sampled = SampledEnvironment::new(&wave_provider(), REGION, 0.1);
+        for ((ix, iy), (x, y)) in REGION.grid_points(0.1) {
+            let node = elevation(sampled.get(ix, iy).unwrap());
+            // at the grid point and anywhere closer to it than to the others
+            for (dx, dy) in [(0.0, 0.0), (0.04, -0.03), (-0.02, 0.04)] {
+                let Some(factors) = sampled.factors_at(x + dx, y + dy, InterpOrder::Nearest) else {
+                    continue;
+                };
+                assert_eq!(elevation(&factors), node);
+            }
+        }
+    }
+
+    #[test]
+    fn bicubic_interpolation_is_smoother_than_bilinear() {
+        let sampled = SampledEnvironment::new(&wave_provider(), REGION, 0.1);
+        // largest second difference along a line across several cells
+        let roughness = |order: InterpOrder| {
+            let elevations = (-120..=120)
+                .map(|i| elevation(&sampled.factors_at(i as f64 * 0.005, 0.03, order).unwrap()))
+                .collect::<Vec<_>>();
+            elevations
+                .windows(3)
+                .map(|e| (e[0] - 2.0 * e[1] + e[2]).abs())
+                .fold(0.0, f64::max)
+        };
+        let bilinear = roughness(InterpOrder::Bilinear);
+        let bicubic = roughness(InterpOrder::Bicubic);
+        assert!(bicubic < bilinear * 0.5, "{bicubic} {bilinear}");
+    }
+
+    #[test]
+    fn two_valleys_give_two_watersheds() {
+        // land everywhere, with sinks at (-0.5, 0.0) and (0.5, 0.0)
+        let provider = land_provider(
+            |x, y| 0.3 + (x * x - 0.25).powi(2) + 0.1 * y * y,
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                primitive_land_octave_amplitudes: Some(vec![1.0]),
+                ..Default::default()
+            },
+        );
+        let sampled = SampledEnvironment::new(&provider, REGION, 0.1);
+        let labels = sampled.watershed_labels();
+        let (cols, _) = sampled.size();
+        assert!(labels.iter().all(|&label| label == 1 || label == 2));
+        for (i, &label) in labels.iter().enumerate() {
+            // the sides of the ridge at x = 0.0
+            match i % cols {
+                0..=9 => assert_eq!(label, labels[0]),
+                11.. => assert_ne!(label, labels[0]),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn supersampling_reduces_the_aliasing() {
+        // detail much finer than the grid
+        let provider = provider_with(
+            WaveNoise {
+                frequency: 37.0,
+                amplitude: 1.0,
+            },
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        );
+        // mean squared difference between horizontally adjacent grid points
+        let roughness = |supersample: u32| {
+            let sampled = SampledEnvironment::new_supersampled(&provider, REGION, 0.1, supersample);
+            let (cols, rows) = sampled.size();
+            let mut sum = 0.0;
+            for iy in 0..rows {
+                for ix in 1..cols {
+                    let a = elevation(sampled.get(ix - 1, iy).unwrap());
+                    let b = elevation(sampled.get(ix, iy).unwrap());
+                    sum += (b - a).powi(2);
+                }
+            }
+            sum / (rows * (cols - 1)) as f64
+        };
+        let single = roughness(1);
+        let supersampled = roughness(4);
+        assert!(supersampled < single * 0.5, "{supersampled} {single}");
+    }
+
+    #[test]
+    fn scrolling_computes_only_the_exposed_cells() {
+        let noise = CountingNoise::default();
+        let provider = provider_with(noise.clone(), ReferenceEnvironmentParameters::default());
+        let mut buffer = ScrollBuffer::new(&provider, REGION, 0.1);
+        let (cols, rows) = buffer.sampled().size();
+        let per_point = noise.take_count() / (cols * rows);
+
+        // 3 columns to the east and 2 rows to the south
+        let computed = buffer.scroll_to(&provider, REGION.min_x + 0.3, REGION.min_y - 0.2);
+        let exposed = cols * rows - (cols - 3) * (rows - 2);
+        assert_eq!(computed, exposed);
+        assert_eq!(noise.take_count(), exposed * per_point);
+        assert_eq!(
+            buffer.scroll_to(&provider, REGION.min_x + 0.3, REGION.min_y - 0.2),
+            0
+        );
+    }
+
+    #[test]
+    fn scrolled_window_matches_a_fresh_sampling() {
+        let provider = provider_with(WaveNoise::default(), params_with_latitude(|_, y| y));
+        let mut buffer = ScrollBuffer::new(&provider, REGION, 0.1);
+        buffer.scroll_to(&provider, REGION.min_x - 0.4, REGION.min_y + 0.1);
+        let scrolled = buffer.sampled();
+        let fresh = SampledEnvironment::new(&provider, scrolled.region(), 0.1);
+        assert_eq!(scrolled.size(), fresh.size());
+        let (cols, rows) = fresh.size();
+        for (ix, iy) in (0..rows).flat_map(|iy| (0..cols).map(move |ix| (ix, iy))) {
+            let (a, b) = (scrolled.get(ix, iy).unwrap(), fresh.get(ix, iy).unwrap());
+            // up to the rounding of the shifted grid coordinates
+            assert!((a.temperature_surface - b.temperature_surface).abs() < 1e-9);
+            assert!((elevation(a) - elevation(b)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn ocean_distance_is_continuous_across_the_coast() {
+        let resolution = 0.02;
+        let sampled = SampledEnvironment::new(&wave_provider(), REGION, resolution);
+        let (cols, rows) = REGION.grid_size(resolution);
+        let distance = sampled.ocean_distance(0.0);
+        assert!(distance.iter().any(|&d| d > 0.0) && distance.iter().any(|&d| d < 0.0));
+
+        let mut crossings = 0;
+        for iy in 0..rows {
+            for ix in 0..cols {
+                let d = distance[iy * cols + ix];
+                let neighbors = [
+                    (ix + 1 < cols, iy * cols + ix + 1),
+                    (iy + 1 < rows, (iy + 1) * cols + ix),
+                ];
+                for (_, n) in neighbors.into_iter().filter(|&(inside, _)| inside) {
+                    // no step larger than the grid step, even where the sign changes
+                    assert!((distance[n] - d).abs() <= resolution + 1e-12);
+                    if (d >= 0.0) != (distance[n] >= 0.0) {
+                        crossings += 1;
+                    }
+                }
+            }
+        }
+        assert!(crossings > 0);
+    }
+}
@@ -0,0 +1,119 @@
+//! Minimal sampling interface for integrating the providers into engines
+
+use crate::*;
+
+/// Anything that gives the factors at a point of the world coordinates
+/// (implemented by every [EnvironmentProvider] and by [SampledEnvironment]),
+/// for consumers that should not depend on the concrete provider type
+pub trait WorldSampler {
+    fn sample(&self, x: f64, y: f64) -> Option<EnvironmentFactors>;
+}
+
+impl<P: EnvironmentProvider + ?Sized> WorldSampler for P {
+    fn sample(&self, x: f64, y: f64) -> Option<EnvironmentFactors> {
+        self.get_factors(x, y)
+    }
+}
+
+/// Interpolated by InterpOrder::default() (see [SampledEnvironment::factors_at])
+impl WorldSampler for SampledEnvironment {
+    fn sample(&self, x: f64, y: f64) -> Option<EnvironmentFactors> {
+        self.factors_at(x, y, InterpOrder::default())
+    }
+}
+
+/// [WorldSampler] from a closure (x, y) -> factors, e.g. a lookup into the storage of an engine
+pub struct FnSampler<F: Fn(f64, f64) -> Option<EnvironmentFactors>>(pub F);
+
+impl<F: Fn(f64, f64) -> Option<EnvironmentFactors>> WorldSampler for FnSampler<F> {
+    fn sample(&self, x: f64, y: f64) -> Option<EnvironmentFactors> {
+        (self.0)(x, y)
+    }
+}
+
+/// [WorldSampler] queried by the index type of an engine (tile, chunk cell, etc.),
+/// converted to the world coordinates by `to_world`
+pub struct IndexedSampler<S: WorldSampler, F> {
+    sampler: S,
+    to_world: F,
+}
+
+impl<S: WorldSampler, F> IndexedSampler<S, F> {
+    pub fn new(sampler: S, to_world: F) -> Self {
+        Self { sampler, to_world }
+    }
+
+    pub fn sampler(&self) -> &S {
+        &self.sampler
+    }
+
+    /// Factors at the world position of `index`
+    pub fn sample_index<I>(&self, index: I) -> Option<EnvironmentFactors>
+    where
+        F: Fn(I) -> (f64, f64),
+    {
+        let (x, y) = (self.to_world)(index);
+        self.sampler.sample(x, y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    /// Consumer generic over the sampler: mean normalized elevation of the valid points
+    fn mean_elevation<S: WorldSampler>(sampler: &S, points: &[(f64, f64)]) -> f64 {
+        let elevations = points
+            .iter()
+            .filter_map(|&(x, y)| sampler.sample(x, y))
+            .map(|factors| factors.primitive_elevation_factors.elevation.normalized)
+            .collect::<Vec<_>>();
+        elevations.iter().sum::<f64>() / elevations.len() as f64
+    }
+
+    #[test]
+    fn generic_consumer_accepts_every_sampler() {
+        let provider = provider_with(
+            WaveNoise::default(),
+            ReferenceEnvironmentParameters {
+                valid_fn: ValidModel::Everywhere.into_fn(),
+                ..Default::default()
+            },
+        );
+        let region = Region {
+            min_x: -1.0,
+            min_y: -0.5,
+            max_x: 1.0,
+            max_y: 0.5,
+        };
+        let points = region
+            .grid_points(0.1)
+            .map(|(_, point)| point)
+            .collect::<Vec<_>>();
+        let expected = mean_elevation(&provider, &points);
+
+        let boxed: Box<dyn EnvironmentProvider + '_> = Box::new(&provider);
+        assert_eq!(mean_elevation(&boxed, &points), expected);
+        let closure = FnSampler(|x, y| provider.get_factors(x, y));
+        assert_eq!(mean_elevation(&closure, &points), expected);
+        // interpolated between the grid points, so exact on them
+        let sampled = SampledEnvironment::new(&provider, region, 0.1);
+        assert!((mean_elevation(&sampled, &points) - expected).abs() < 1e-9);
+
+        // tiles of 0.1 with the tile (0, 0) at the corner of the region
+        let tiles = IndexedSampler::new(&provider, |(col, row): (i32, i32)| {
+            (
+                region.min_x + col as f64 * 0.1,
+                region.min_y + row as f64 * 0.1,
+            )
+        });
+        let tile = tiles.sample_index((3, 2)).unwrap();
+        let point = provider.get_factors(-0.7, -0.3).unwrap();
+        assert!(factors_approx_eq(
+            &tile,
+            &point,
+            &FactorTolerance::default()
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+//! Analytic noise sources and providers shared by the tests
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use crate::{noise::*, *};
+
+/// Noise source returning 0.0 and counting its samples
+/// (in a counter shared by its clones)
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CountingNoise {
+    count: Arc<AtomicUsize>,
+}
+
+impl CountingNoise {
+    /// Number of samples taken since the last call
+    pub(crate) fn take_count(&self) -> usize {
+        self.count.swap(0, Ordering::Relaxed)
+    }
+}
+
+impl NoiseSource for CountingNoise {
+    fn sample(&self, _x: f64, _y: f64) -> f64 {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        0.0
+    }
+}
+
+/// Provider whose noise channels are all `noise`
+pub(crate) fn provider_with<N: NoiseSource + Clone>(
+    noise: N,
+    params: ReferenceEnvironmentParameters,
+) -> ReferenceEnvironmentProvider<N> {
+    ReferenceEnvironmentProvider::with_noises(vec![noise; NOISE_END], params)
+}
+
+/// Smooth analytic noise in [-amplitude, amplitude] with structure on every axis
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WaveNoise {
+    pub frequency: f64,
+    pub amplitude: f64,
+}
+
+impl Default for WaveNoise {
+    fn default() -> Self {
+        Self {
+            frequency: 1.7,
+            amplitude: 1.0,
+        }
+    }
+}
+
+impl NoiseSource for WaveNoise {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        (x * self.frequency).sin() * (y * self.frequency * 1.3 + 0.4).cos() * self.amplitude
+    }
+}
+
+/// Noise source sampling a closure
+pub(crate) struct FnNoise(pub Box<dyn Fn(f64, f64) -> f64 + Send + Sync>);
+
+impl NoiseSource for FnNoise {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        (self.0)(x, y)
+    }
+}
+
+/// Provider whose noise channels are given by `channels` (channel, noise),
+/// the other channels being 0.0
+pub(crate) fn provider_with_channels(
+    channels: Vec<(usize, FnNoise)>,
+    params: ReferenceEnvironmentParameters,
+) -> ReferenceEnvironmentProvider<FnNoise> {
+    let mut noises = (0..NOISE_END)
+        .map(|_| FnNoise(Box::new(|_, _| 0.0)))
+        .collect::<Vec<_>>();
+    for (channel, noise) in channels {
+        noises[channel] = noise;
+    }
+    ReferenceEnvironmentProvider::with_noises(noises, params)
+}
+
+/// Provider whose land is `land` (the continent noise is 1.0, so the shelf is 0.0 and
+/// the elevation is the shaped land everywhere)
+pub(crate) fn land_provider(
+    land: impl Fn(f64, f64) -> f64 + Send + Sync + 'static,
+    params: ReferenceEnvironmentParameters,
+) -> ReferenceEnvironmentProvider<FnNoise> {
+    provider_with_channels(
+        vec![
+            (NOISE_PRIMITIVE_CONTINENT, FnNoise(Box::new(|_, _| 1.0))),
+            (NOISE_PRIMITIVE_LAND, FnNoise(Box::new(land))),
+        ],
+        params,
+    )
+}
+
+/// Provider whose noise channels are all 0.0 (a flat ocean floor)
+pub(crate) fn flat_provider(
+    params: ReferenceEnvironmentParameters,
+) -> ReferenceEnvironmentProvider<PlaneNoise> {
+    provider_with(PlaneNoise::default(), params)
+}
+
+/// Parameters valid everywhere whose virtual_latitude is `latitude_fn`
+pub(crate) fn params_with_latitude(
+    latitude_fn: impl Fn(f64, f64) -> f64 + Send + Sync + 'static,
+) -> ReferenceEnvironmentParameters {
+    ReferenceEnvironmentParameters {
+        virtual_latitude_fn: Box::new(latitude_fn),
+        valid_fn: ValidModel::Everywhere.into_fn(),
+        ..Default::default()
+    }
+}
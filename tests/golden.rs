@@ -0,0 +1,186 @@
+//! Golden tests of [ReferenceEnvironmentProvider]: a fixed grid is sampled and compared
+//! against the values committed in tests/golden/<name>.txt.
+//!
+//! A change of the generation math that moves any factor beyond GOLDEN_TOLERANCE fails
+//! these tests. If the change is intended, regenerate the files with
+//! `BLESS_GOLDEN=1 cargo test --test golden` and commit them together with the change.
+//!
+//! The grids are sampled on analytic noise so that the files don't depend on the noise
+//! library; a grid on the seeded noise can be added with [check_golden] once its file is
+//! blessed against libnoise.
+
+use std::{fmt::Write, path::PathBuf};
+
+use environment_builder::{
+    diff_factors, factors_approx_eq, EnvironmentFactors, EnvironmentProvider, FactorTolerance,
+    NoiseSource, PrimitiveElevationFactors, ReferenceEnvironmentParameters,
+    ReferenceEnvironmentProvider, Region, ValueWithNormalized,
+};
+
+/// Tolerance of the comparison, loose enough for the differences of the floating point
+/// functions between platforms
+const GOLDEN_TOLERANCE: f64 = 1e-6;
+
+/// Number of noise channels of [ReferenceEnvironmentProvider]
+const CHANNELS: usize = 10;
+
+/// Region of the golden grids (within the valid band |y| < 1.0 of the default parameters)
+const REGION: Region = Region {
+    min_x: -1.0,
+    min_y: -0.75,
+    max_x: 1.0,
+    max_y: 0.75,
+};
+
+const RESOLUTION: f64 = 0.25;
+
+/// Smooth analytic noise, independent of the noise library
+#[derive(Clone, Copy)]
+struct WaveNoise {
+    phase: f64,
+}
+
+impl NoiseSource for WaveNoise {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        (x * 1.7 + self.phase).sin() * (y * 2.3 - self.phase).cos() * 0.8
+    }
+}
+
+fn wave_provider(
+    params: ReferenceEnvironmentParameters,
+) -> ReferenceEnvironmentProvider<WaveNoise> {
+    let noises = (0..CHANNELS)
+        .map(|channel| WaveNoise {
+            phase: channel as f64 * 0.7,
+        })
+        .collect();
+    ReferenceEnvironmentProvider::with_noises(noises, params)
+}
+
+/// Samples `provider` on the grid sampling `region` every `resolution` and compares the
+/// factors against tests/golden/`name`.txt (or writes the file if BLESS_GOLDEN is set).
+/// Usable for any provider, e.g. presets of the parameters.
+fn check_golden(name: &str, provider: &impl EnvironmentProvider, region: Region, resolution: f64) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.txt"));
+    let samples = region
+        .grid_points(resolution)
+        .map(|(_, (x, y))| ((x, y), provider.get_factors(x, y)))
+        .collect::<Vec<_>>();
+
+    if std::env::var_os("BLESS_GOLDEN").is_some() {
+        let mut text = format!("# golden factors of {name} (see tests/golden.rs)\n");
+        for (_, factors) in &samples {
+            text += &factors
+                .as_ref()
+                .map_or("invalid".to_string(), format_factors);
+            text += "\n";
+        }
+        std::fs::write(&path, text).expect("failed to write the golden file");
+        return;
+    }
+
+    let text = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "missing golden file {}; generate it with `BLESS_GOLDEN=1 cargo test --test golden`",
+            path.display()
+        )
+    });
+    let expected = text
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| (line != "invalid").then(|| parse_factors(line)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        expected.len(),
+        samples.len(),
+        "{name}: the golden grid has a different size"
+    );
+
+    let tolerance = FactorTolerance::uniform(GOLDEN_TOLERANCE);
+    let mut report = String::new();
+    for (((x, y), actual), expected) in samples.iter().zip(&expected) {
+        match (actual, expected) {
+            (Some(actual), Some(expected)) if !factors_approx_eq(expected, actual, &tolerance) => {
+                let diff = diff_factors(expected, actual);
+                writeln!(report, "({x}, {y}): changed by {diff:?}").unwrap();
+            }
+            (Some(_), Some(_)) | (None, None) => {}
+            (actual, _) => {
+                let validity = if actual.is_some() { "valid" } else { "invalid" };
+                writeln!(report, "({x}, {y}): now {validity}").unwrap();
+            }
+        }
+    }
+    assert!(
+        report.is_empty(),
+        "{name} drifted from its golden values (bless them with BLESS_GOLDEN=1 if the change is intended):\n{report}"
+    );
+}
+
+fn format_factors(factors: &EnvironmentFactors) -> String {
+    let p = &factors.primitive_elevation_factors;
+    let option = |value: Option<f64>| value.map_or("-".to_string(), |value| format!("{value:?}"));
+    [
+        format!("{:?}", factors.virtual_latitude),
+        format!("{:?}", factors.temperature_surface),
+        format!("{:?}", factors.diurnal_range),
+        option(factors.sea_level_pressure_normalized),
+        option(factors.atmosphere_pressure_normalized),
+        option(factors.atmosphere_current_angle),
+        option(factors.atmosphere_current_magnitude),
+        option(factors.atmosphere_current_speed),
+        format!("{:?}", p.shelf),
+        format!("{:?}", p.persistence.value),
+        format!("{:?}", p.persistence.normalized),
+        format!("{:?}", p.land_base),
+        format!("{:?}", p.elevation.value),
+        format!("{:?}", p.elevation.normalized),
+        option(factors.ocean_current_angle),
+        option(factors.ocean_current_magnitude),
+    ]
+    .join(" ")
+}
+
+fn parse_factors(line: &str) -> EnvironmentFactors {
+    let values = line
+        .split_whitespace()
+        .map(|value| match value {
+            "-" => None,
+            value => Some(value.parse::<f64>().expect("malformed golden value")),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(values.len(), 16, "malformed golden line: {line}");
+    let value = |i: usize| values[i].expect("missing golden value");
+    EnvironmentFactors {
+        virtual_latitude: value(0),
+        temperature_surface: value(1),
+        diurnal_range: value(2),
+        sea_level_pressure_normalized: values[3],
+        atmosphere_pressure_normalized: values[4],
+        atmosphere_current_angle: values[5],
+        atmosphere_current_magnitude: values[6],
+        atmosphere_current_speed: values[7],
+        primitive_elevation_factors: PrimitiveElevationFactors {
+            shelf: value(8),
+            persistence: ValueWithNormalized {
+                value: value(9),
+                normalized: value(10),
+            },
+            land_base: value(11),
+            elevation: ValueWithNormalized {
+                value: value(12),
+                normalized: value(13),
+            },
+        },
+        ocean_current_angle: values[14],
+        ocean_current_magnitude: values[15],
+    }
+}
+
+#[test]
+fn golden_default_parameters_on_analytic_noise() {
+    let provider = wave_provider(ReferenceEnvironmentParameters::default());
+    check_golden("default_analytic", &provider, REGION, RESOLUTION);
+}